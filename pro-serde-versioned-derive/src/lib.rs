@@ -8,248 +8,1736 @@
 // │  ╚═╝     ╚═╝  ╚═╝ ╚═════╝   strictly prohibited.                          │
 // │                                                                           │
 // └───────────────────────────────────────────────────────────────────────────┘
-use std::collections::HashMap;
+use std::collections::BTreeMap;
 
 use proc_macro::TokenStream;
-use quote::quote;
-use syn::{Data, DeriveInput, Fields};
+use quote::{format_ident, quote, ToTokens};
+use syn::spanned::Spanned;
+use syn::{parse_macro_input, Attribute, Data, DeriveInput, Fields};
+
+/// A versioned enum's options and its variants, keyed by version number.
+struct VersionedEnum {
+    attrs: ContainerAttrs,
+    variants: BTreeMap<usize, VersionVariant>,
+    /// The `#[versioned(unknown)]` variant, which holds the `RawEnvelope` of
+    /// every other version.
+    unknown_variant: Option<UnknownVariant>,
+}
+
+/// The variant marked `#[versioned(unknown)]`, e.g.
+/// `Unknown(RawEnvelope<serde_json::Value>)`.
+struct UnknownVariant {
+    ident: syn::Ident,
+    /// The raw data its `RawEnvelope` holds, e.g. `serde_json::Value`, which
+    /// is the only format it can be read from and written to.
+    raw_ty: syn::Type,
+}
 
 #[derive(Debug)]
 struct VersionVariant {
     version_number: usize,
     variant_ident: syn::Ident,
+    /// The type which the upgrade and downgrade steps are implemented on: the
+    /// wrapped type of a newtype variant, or else its generated payload struct.
     variant_ty: syn::Type,
+    fields: VariantFields,
+    /// The name declared with `#[versioned(version = "..")]`, which is written
+    /// to the envelope in place of the version number.
+    version_name: Option<syn::LitStr>,
     latest: bool,
 }
 
-#[proc_macro_derive(VersionedSerialize)]
+/// A version declared with `#[versioned(version = ..)]`, or the variant marked
+/// `#[versioned(unknown)]`.
+enum VersionAttr {
+    Number(usize),
+    Name(syn::LitStr),
+    Unknown,
+}
+
+/// The shape of a variant's data.
+#[derive(Debug)]
+enum VariantFields {
+    /// `V1(MyStructV1)`, which wraps a type of its own.
+    Newtype,
+    /// `V2 { a: u32, b: String }`
+    Named(Vec<syn::Field>),
+    /// `V2(u32, String)`
+    Unnamed(Vec<syn::Field>),
+    /// `V0`
+    Unit,
+}
+
+impl VersionVariant {
+    /// Binds the variant's fields to local variables, as either a pattern or an
+    /// expression which rebuilds it. A newtype variant binds its data to
+    /// `value`.
+    fn destructure(&self, path: proc_macro2::TokenStream) -> proc_macro2::TokenStream {
+        match &self.fields {
+            VariantFields::Newtype => quote! { #path(value) },
+            VariantFields::Named(fields) => {
+                let field_names = fields.iter().map(|field| &field.ident);
+                quote! { #path { #(#field_names),* } }
+            }
+            VariantFields::Unnamed(fields) => {
+                let field_names = (0..fields.len()).map(|index| format_ident!("field_{}", index));
+                quote! { #path(#(#field_names),*) }
+            }
+            VariantFields::Unit => path,
+        }
+    }
+
+    /// A pattern matching this variant of `name`, which binds its fields.
+    fn variant_pattern(&self, name: &syn::Ident) -> proc_macro2::TokenStream {
+        let variant_ident = &self.variant_ident;
+        self.destructure(quote! { #name::#variant_ident })
+    }
+
+    /// An expression for the variant's data as a `variant_ty`, from the fields
+    /// bound by `variant_pattern`.
+    fn payload(&self) -> proc_macro2::TokenStream {
+        match &self.fields {
+            VariantFields::Newtype => quote! { value },
+            _ => {
+                let variant_ty = &self.variant_ty;
+                self.destructure(quote! { #variant_ty })
+            }
+        }
+    }
+
+    /// An expression which wraps `payload`, a `variant_ty`, in this variant of
+    /// `name`.
+    fn wrap_payload(
+        &self,
+        name: &syn::Ident,
+        payload: proc_macro2::TokenStream,
+    ) -> proc_macro2::TokenStream {
+        let variant_ident = &self.variant_ident;
+        match &self.fields {
+            VariantFields::Newtype => quote! { #name::#variant_ident(#payload) },
+            _ => {
+                let payload_pattern = self.payload();
+                let variant = self.variant_pattern(name);
+                quote! {{
+                    let #payload_pattern = #payload;
+                    #variant
+                }}
+            }
+        }
+    }
+}
+
+#[proc_macro_derive(Versioned, attributes(versioned))]
+pub fn versioned(input: TokenStream) -> TokenStream {
+    let ast = parse_macro_input!(input as DeriveInput);
+    expand_versioned(&ast)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+#[proc_macro_derive(VersionedSerialize, attributes(versioned))]
 pub fn versioned_serialize(input: TokenStream) -> TokenStream {
-    let ast: DeriveInput = syn::parse(input).unwrap();
+    let ast = parse_macro_input!(input as DeriveInput);
+    expand_versioned_serialize(&ast)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+#[proc_macro_derive(VersionedDeserialize, attributes(versioned))]
+pub fn versioned_deserialize(input: TokenStream) -> TokenStream {
+    let ast = parse_macro_input!(input as DeriveInput);
+    expand_versioned_deserialize(&ast)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+#[proc_macro_derive(VersionedUpgrade, attributes(versioned))]
+pub fn upgradable_enum(input: TokenStream) -> TokenStream {
+    let ast = parse_macro_input!(input as DeriveInput);
+    expand_versioned_upgrade(&ast)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+#[proc_macro_derive(VersionedDowngrade, attributes(versioned))]
+pub fn downgradable_enum(input: TokenStream) -> TokenStream {
+    let ast = parse_macro_input!(input as DeriveInput);
+    expand_versioned_downgrade(&ast)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+/// Generates the items which belong to the enum as a whole rather than to any
+/// one of the traits, i.e. the payload structs and the `VersionOf` impls, so
+/// that each is generated exactly once however the other derives are combined.
+fn expand_versioned(ast: &DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
     let name = &ast.ident;
-    let version_variants = get_version_variants(&ast);
-    let variant_names: Vec<_> = version_variants
+    let VersionedEnum {
+        variants: version_variants,
+        ..
+    } = parse_versioned_enum(ast)?;
+    let (impl_generics, ty_generics, where_clause) = ast.generics.split_for_impl();
+    let version_of_impls = version_variants.values().map(|version_variant| {
+        let variant_ty = &version_variant.variant_ty;
+        let variant_pattern = version_variant.variant_pattern(name);
+        let payload = version_variant.payload();
+        let version_number = version_variant.version_number;
+        quote! {
+            impl #impl_generics ::pro_serde_versioned::VersionOf<#name #ty_generics> for #variant_ty #where_clause {
+                const VERSION: usize = #version_number;
+
+                #[allow(unreachable_patterns)]
+                fn from_versioned(
+                    versioned: #name #ty_generics,
+                ) -> ::core::result::Result<Self, #name #ty_generics> {
+                    match versioned {
+                        #variant_pattern => ::core::result::Result::Ok(#payload),
+                        versioned => ::core::result::Result::Err(versioned),
+                    }
+                }
+            }
+        }
+    });
+
+    let payload_structs = generate_payload_structs(ast, &version_variants);
+
+    Ok(quote! {
+        #payload_structs
+        #(#version_of_impls)*
+    })
+}
+
+fn expand_versioned_serialize(ast: &DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
+    let name = &ast.ident;
+    let VersionedEnum {
+        attrs: container_attrs,
+        variants: version_variants,
+        unknown_variant,
+    } = parse_versioned_enum(ast)?;
+
+    // Only newtype variants wrap a type of their own to convert from.
+    let newtype_variants: Vec<_> = version_variants
         .values()
+        .filter(|version_variant| matches!(version_variant.fields, VariantFields::Newtype))
+        .collect();
+    let newtype_names: Vec<_> = newtype_variants
+        .iter()
         .map(|version_variant| &version_variant.variant_ident)
-        .cloned()
         .collect();
 
-    let variant_tys: Vec<_> = version_variants
+    let newtype_tys: Vec<_> = newtype_variants
+        .iter()
+        .map(|version_variant| &version_variant.variant_ty)
+        .collect();
+
+    let variant_patterns: Vec<_> = version_variants
         .values()
-        .map(|version_variant| version_variant.variant_ty.clone())
+        .map(|version_variant| version_variant.variant_pattern(name))
         .collect();
 
-    let variant_versions: Vec<_> = version_variants
+    let variant_envelopes: Vec<_> = version_variants
         .values()
-        .map(|version_variant| version_variant.version_number)
+        .map(|version_variant| {
+            let version_number = version_variant.version_number;
+            let data = serialize_variant_data(version_variant, |payload| {
+                quote! { F::serialize_format(#payload) }
+            });
+
+            quote! {
+                <Self::VersionedEnvelope<F> as ::pro_serde_versioned::Envelope<F>>::from_parts(
+                    #version_number,
+                    #data?,
+                )
+            }
+        })
         .collect();
 
-    let generics = ast.generics;
-    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
-    quote! {
-        #(
-            impl #impl_generics From<#variant_tys> for #name #ty_generics #where_clause {
-                fn from(value: #variant_tys) -> #name {
-                    #name::#variant_names(value)
+    // Unknown versions are written back out as they were read, by formats
+    // whose raw data the variant holds, for which alone `WritesUnknown` is
+    // implemented.
+    let unknown_envelope = unknown_variant.as_ref().map(|unknown_variant| {
+        let unknown_variant = &unknown_variant.ident;
+        quote! {
+            #name::#unknown_variant(_) => {
+                match <Self as ::pro_serde_versioned::WritesUnknown<F::Raw>>::unknown(self) {
+                    ::core::option::Option::Some(raw) => raw
+                        .to_envelope::<F, Self::VersionedEnvelope<F>, F::Error>(
+                            <F as ::pro_serde_versioned::SerializeFormat>::from_raw(&raw.data),
+                        ),
+                    ::core::option::Option::None => ::core::unreachable!(),
                 }
             }
+        }
+    });
+
+    let writes_unknown_impl = unknown_impl(
+        ast,
+        unknown_variant.as_ref(),
+        quote! { ::pro_serde_versioned::WritesUnknown },
+        |unknown_variant, raw_ty| {
+            quote! {
+                fn unknown(
+                    &self,
+                ) -> ::core::option::Option<&::pro_serde_versioned::RawEnvelope<#raw_ty>> {
+                    match self {
+                        #name::#unknown_variant(raw) => ::core::option::Option::Some(raw),
+                        _ => ::core::option::Option::None,
+                    }
+                }
+            }
+        },
+    );
 
-            // // TODO This would be handy, but will create conflicts when
-            // // multiple enums use `#variant_tys`, as we do in the tests.
+    let envelope_consts = container_attrs.envelope_consts(&version_variants);
+    let envelope_ty = container_attrs.envelope_ty(&version_variants, quote! { A });
+    let (impl_generics, ty_generics, where_clause) = ast.generics.split_for_impl();
 
-            // impl VersionedSerialize for #variant_tys {
-            //     fn versioned_serialize<F: SerializeFormat>(&self) -> Result<F, Box<dyn std::error::Error>> {
-            //         #[derive(Serialize, Deserialize)]
-            //         pub struct VersionedEnvelope<T> {
-            //             pub version_number: usize,
-            //             pub data: T,
-            //         }
+    // The same envelopes, holding the data itself rather than a format, which
+    // are serialized straight to the serializer.
+    let streamed_envelope_ty = container_attrs.envelope_ty(&version_variants, quote! { _ });
+    let unknown_serialization = unknown_variant.as_ref().map(|unknown_variant| {
+        let unknown_variant = &unknown_variant.ident;
+        quote! {
+            #name::#unknown_variant(raw) => {
+                let envelope: #streamed_envelope_ty = raw.to_envelope::<_, _, __S::Error>(&raw.data)?;
+                ::pro_serde_versioned::serde::Serialize::serialize(&envelope, __serializer)
+            }
+        }
+    });
 
-            //         let envelope = VersionedEnvelope {
-            //             version_number: #variant_versions,
-            //             data: F::serialize_format(&self)?
-            //         };
+    let variant_serializations = version_variants.values().map(|version_variant| {
+        let version_number = version_variant.version_number;
+        serialize_variant_data(version_variant, |payload| {
+            quote! {{
+                let envelope: #streamed_envelope_ty =
+                    ::pro_serde_versioned::Envelope::from_parts(#version_number, #payload);
+                ::pro_serde_versioned::serde::Serialize::serialize(&envelope, __serializer)
+            }}
+        })
+    });
+
+    let serde_impl = container_attrs.serde.then(|| {
+        let generics = bounded_generics(
+            &ast.generics,
+            quote! { ::pro_serde_versioned::serde::Serialize },
+        );
+        let (impl_generics, _, where_clause) = generics.split_for_impl();
+        quote! {
+            impl #impl_generics ::pro_serde_versioned::serde::Serialize for #name #ty_generics #where_clause {
+                fn serialize<__S: ::pro_serde_versioned::serde::Serializer>(
+                    &self,
+                    __serializer: __S,
+                ) -> ::core::result::Result<__S::Ok, __S::Error> {
+                    ::pro_serde_versioned::VersionedSerialize::versioned_serialize_into(
+                        self,
+                        __serializer,
+                    )
+                }
+            }
+        }
+    });
 
-            //         F::serialize_format(envelope)
-            //     }
-            // }
+    Ok(quote! {
+        #(
+            impl #impl_generics ::core::convert::From<#newtype_tys> for #name #ty_generics #where_clause {
+                fn from(value: #newtype_tys) -> #name #ty_generics {
+                    #name::#newtype_names(value)
+                }
+            }
         )*
 
         impl #impl_generics ::pro_serde_versioned::VersionedSerialize for #name #ty_generics #where_clause {
-            type VersionedEnvelope<A: Serialize> = ::pro_serde_versioned::VersionedEnvelope<A>;
-            fn to_envelope<F: ::pro_serde_versioned::SerializeFormat>(&self) -> Result<Self::VersionedEnvelope<F>, F::Error> {
+            #envelope_consts
+
+            type VersionedEnvelope<A: ::pro_serde_versioned::serde::Serialize> = #envelope_ty;
+
+            fn to_envelope<F>(&self) -> ::core::result::Result<Self::VersionedEnvelope<F>, F::Error>
+            where
+                Self: ::pro_serde_versioned::WritesUnknown<F::Raw>,
+                F: ::pro_serde_versioned::SerializeFormat,
+            {
                 match self {
                     #(
-                        #name::#variant_names(value) => {
-                            Ok(::pro_serde_versioned::VersionedEnvelope {
-                                version_number: #variant_versions,
-                                data: F::serialize_format(&value)?
-                            })
-                        }
+                        #variant_patterns => ::core::result::Result::Ok(#variant_envelopes),
+                    )*
+                    #unknown_envelope
+                }
+            }
+
+            fn versioned_serialize_into<__S: ::pro_serde_versioned::serde::Serializer>(
+                &self,
+                __serializer: __S,
+            ) -> ::core::result::Result<__S::Ok, __S::Error> {
+                match self {
+                    #(
+                        #variant_patterns => #variant_serializations,
                     )*
+                    #unknown_serialization
                 }
             }
         }
+
+        #writes_unknown_impl
+        #serde_impl
+    })
+}
+
+/// Serializes the data of a variant, whose fields are bound by reference, by
+/// passing it to `serialize`. Fields which are not wrapped in a type of their
+/// own are serialized as the variant's payload struct would be.
+fn serialize_variant_data(
+    version_variant: &VersionVariant,
+    serialize: impl Fn(proc_macro2::TokenStream) -> proc_macro2::TokenStream,
+) -> proc_macro2::TokenStream {
+    let payload_name = version_variant.variant_ty.to_token_stream().to_string();
+    match &version_variant.fields {
+        VariantFields::Newtype => serialize(quote! { value }),
+        VariantFields::Named(fields) => {
+            let field_names: Vec<_> = fields.iter().map(|field| &field.ident).collect();
+            let field_tys = fields.iter().map(|field| &field.ty);
+            let serialize_payload = serialize(quote! { __VersionedPayload { #(#field_names),* } });
+            quote! {{
+                #[derive(::pro_serde_versioned::serde::Serialize)]
+                #[serde(crate = "::pro_serde_versioned::serde", rename = #payload_name)]
+                struct __VersionedPayload<'a> {
+                    #(#field_names: &'a #field_tys),*
+                }
+
+                #serialize_payload
+            }}
+        }
+        VariantFields::Unnamed(fields) => {
+            let field_names = (0..fields.len()).map(|index| format_ident!("field_{}", index));
+            let field_tys = fields.iter().map(|field| &field.ty);
+            let serialize_payload = serialize(quote! { __VersionedPayload(#(#field_names),*) });
+            quote! {{
+                #[derive(::pro_serde_versioned::serde::Serialize)]
+                #[serde(crate = "::pro_serde_versioned::serde", rename = #payload_name)]
+                struct __VersionedPayload<'a>(#(&'a #field_tys),*);
+
+                #serialize_payload
+            }}
+        }
+        VariantFields::Unit => serialize(quote! { () }),
     }
-    .into()
 }
 
-#[proc_macro_derive(VersionedDeserialize)]
-pub fn versioned_deserialize(input: TokenStream) -> TokenStream {
-    let ast: DeriveInput = syn::parse(input).unwrap();
+fn expand_versioned_deserialize(ast: &DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
     let name = &ast.ident;
 
-    let version_variants = get_version_variants(&ast);
-    let variant_names: Vec<_> = version_variants
+    let VersionedEnum {
+        attrs: container_attrs,
+        variants: version_variants,
+        unknown_variant,
+    } = parse_versioned_enum(ast)?;
+    let variant_values: Vec<_> = version_variants
         .values()
-        .map(|version_variant| &version_variant.variant_ident)
-        .cloned()
+        .map(|version_variant| {
+            deserialize_variant(name, version_variant, quote! {
+                <F as ::pro_serde_versioned::DeserializeFormat>::deserialize_format(&envelope.data)?
+            })
+        })
         .collect();
 
+    // Unsuffixed, so that they match the envelope's version number whatever its
+    // `repr`.
     let variant_versions: Vec<_> = version_variants
-        .values()
-        .map(|version_variant| version_variant.version_number)
+        .keys()
+        .map(|version_number| proc_macro2::Literal::usize_unsuffixed(*version_number))
         .collect();
 
-    let generics = ast.generics;
-    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+    let versioned_deserialize = container_attrs
+        .unversioned_version(&version_variants)
+        .map(|unversioned| {
+        let unversioned_value = deserialize_variant(
+            name,
+            &version_variants[&unversioned.expect("checked version")],
+            quote! { <F as ::pro_serde_versioned::DeserializeFormat>::deserialize_format(data)? },
+        );
 
-    quote! {
+        quote! {
+            fn versioned_deserialize<'a, F>(data: &'a F) -> ::core::result::Result<Self, F::Error>
+            where
+                Self: ::pro_serde_versioned::KeepsUnknown<F::Raw>,
+                F: ::pro_serde_versioned::DeserializeFormat + ::pro_serde_versioned::serde::Deserialize<'a>,
+            {
+                match <F as ::pro_serde_versioned::DeserializeFormat>::deserialize_format::<
+                    Self::VersionedEnvelope<'a, F>,
+                >(data) {
+                    ::core::result::Result::Ok(envelope) => Self::from_envelope(&envelope),
+                    ::core::result::Result::Err(error) => {
+                        match ::pro_serde_versioned::probe_version::<Self, F>(data) {
+                            ::pro_serde_versioned::ProbedVersion::Absent => {
+                                ::core::result::Result::Ok(#unversioned_value)
+                            }
+                            version => ::core::result::Result::Err(
+                                version.unknown_version::<Self>().map_or(
+                                    error,
+                                    <F::Error as ::pro_serde_versioned::serde::de::Error>::custom,
+                                ),
+                            ),
+                        }
+                    }
+                }
+            }
+        }
+    });
+
+    // Versions the enum does not have are kept undecoded by its `unknown`
+    // variant, if it has one.
+    let unknown_value = match &unknown_variant {
+        Some(_) => quote! {
+            <Self as ::pro_serde_versioned::KeepsUnknown<F::Raw>>::keep_unknown(
+                ::pro_serde_versioned::RawEnvelope {
+                    version: ::pro_serde_versioned::Envelope::<F>::version(envelope),
+                    data: <F as ::pro_serde_versioned::DeserializeFormat>::to_raw(&envelope.data),
+                },
+            )
+            .ok_or_else(|| {
+                <F::Error as ::pro_serde_versioned::serde::de::Error>::custom(
+                    ::pro_serde_versioned::VersionedError::unknown_version::<Self>(
+                        ::pro_serde_versioned::Envelope::<F>::version(envelope),
+                    ),
+                )
+            })
+        },
+        None => quote! {
+            ::core::result::Result::Err(
+                <F::Error as ::pro_serde_versioned::serde::de::Error>::custom(
+                    ::pro_serde_versioned::VersionedError::unknown_version::<Self>(
+                        ::pro_serde_versioned::Envelope::<F>::version(envelope),
+                    ),
+                )
+            )
+        },
+    };
+
+    let keeps_unknown_impl = unknown_impl(
+        ast,
+        unknown_variant.as_ref(),
+        quote! { ::pro_serde_versioned::KeepsUnknown },
+        |unknown_variant, raw_ty| {
+            quote! {
+                fn keep_unknown(
+                    raw: ::pro_serde_versioned::RawEnvelope<#raw_ty>,
+                ) -> ::core::option::Option<Self> {
+                    ::core::option::Option::Some(#name::#unknown_variant(raw))
+                }
+            }
+        },
+    );
+
+    let keeps_unknown_versions = unknown_variant.is_some().then(|| {
+        quote! {
+            const KEEPS_UNKNOWN_VERSIONS: bool = true;
+        }
+    });
+
+    let envelope_consts = container_attrs.envelope_consts(&version_variants);
+    let envelope_ty = container_attrs.envelope_ty(&version_variants, quote! { F });
+    let (impl_generics, ty_generics, where_clause) = ast.generics.split_for_impl();
+
+    let serde_impl = container_attrs.serde.then(|| {
+        let unknown_version_value = match &unknown_variant {
+            Some(UnknownVariant {
+                ident: unknown_variant,
+                ..
+            }) => quote! {
+                ::core::result::Result::Ok(#name::#unknown_variant(
+                    ::pro_serde_versioned::RawEnvelope {
+                        version,
+                        data: ::pro_serde_versioned::serde::Deserialize::deserialize(__deserializer)?,
+                    },
+                ))
+            },
+            None => quote! {
+                ::core::result::Result::Err(
+                    <__D::Error as ::pro_serde_versioned::serde::de::Error>::custom(
+                        ::pro_serde_versioned::VersionedError::unknown_version::<Self>(
+                            version,
+                        ),
+                    )
+                )
+            },
+        };
+
+        let version_values = version_variants.values().map(|version_variant| {
+            deserialize_variant(name, version_variant, quote! {
+                ::pro_serde_versioned::serde::Deserialize::deserialize(__deserializer)?
+            })
+        });
+
+        let deserialize_enveloped = if container_attrs.internally_tagged.is_some() {
+            quote! { ::pro_serde_versioned::deserialize_internally_tagged }
+        } else {
+            quote! { ::pro_serde_versioned::deserialize_enveloped }
+        };
+
+        let repr = container_attrs.repr_ty();
+        let mut generics = bounded_generics(
+            &ast.generics,
+            quote! { ::pro_serde_versioned::serde::Deserialize<'de> },
+        );
+        generics.params.insert(0, syn::parse_quote!('de));
+        let (impl_generics, _, where_clause) = generics.split_for_impl();
+        quote! {
+            impl #impl_generics ::pro_serde_versioned::DeserializeVersion<'de> for #name #ty_generics #where_clause {
+                fn deserialize_version<__D: ::pro_serde_versioned::serde::Deserializer<'de>>(
+                    version: ::pro_serde_versioned::RawVersion,
+                    __deserializer: __D,
+                ) -> ::core::result::Result<Self, __D::Error> {
+                    match version {
+                        #(
+                            ::pro_serde_versioned::RawVersion::Number(#variant_versions) => {
+                                ::core::result::Result::Ok(#version_values)
+                            }
+                        )*
+                        _ => #unknown_version_value,
+                    }
+                }
+            }
+
+            impl #impl_generics ::pro_serde_versioned::serde::Deserialize<'de> for #name #ty_generics #where_clause {
+                fn deserialize<__D: ::pro_serde_versioned::serde::Deserializer<'de>>(
+                    __deserializer: __D,
+                ) -> ::core::result::Result<Self, __D::Error> {
+                    #deserialize_enveloped::<Self, #repr, __D>(__deserializer)
+                }
+            }
+        }
+    });
+
+    Ok(quote! {
         impl #impl_generics ::pro_serde_versioned::VersionedDeserialize for #name #ty_generics #where_clause {
-            type VersionedEnvelope<'a, F: Deserialize<'a>> = ::pro_serde_versioned::VersionedEnvelope<F>;
-            fn from_envelope<'a, F: ::pro_serde_versioned::DeserializeFormat + Deserialize<'a>>(
-                envelope: &::pro_serde_versioned::VersionedEnvelope<F>,
-            ) -> Result<Self, F::Error> {
+            #envelope_consts
+            #versioned_deserialize
+
+            const VERSIONS: &'static [usize] = &[#(#variant_versions),*];
+            #keeps_unknown_versions
+
+            type VersionedEnvelope<'a, F: ::pro_serde_versioned::serde::Deserialize<'a>> =
+                #envelope_ty;
+
+            fn from_envelope<'a, F>(
+                envelope: &Self::VersionedEnvelope<'a, F>,
+            ) -> ::core::result::Result<Self, F::Error>
+            where
+                Self: ::pro_serde_versioned::KeepsUnknown<F::Raw>,
+                F: ::pro_serde_versioned::DeserializeFormat + ::pro_serde_versioned::serde::Deserialize<'a>,
+            {
                 match envelope.version_number {
                     #(
-                        #variant_versions => Ok(#name::#variant_names(
-                            <F as ::pro_serde_versioned::DeserializeFormat>::deserialize_format(
-                                &envelope.data
-                            )
-                        ?)),
+                        #variant_versions => ::core::result::Result::Ok(#variant_values),
                     )*
-                    _ => Err(serde::de::Error::custom("Unknown version number")),
+                    _ => #unknown_value,
                 }
             }
         }
+
+        #keeps_unknown_impl
+        #serde_impl
+    })
+}
+
+/// Builds a variant of `name` from the data deserialized by `deserialize_data`,
+/// an expression of whichever type the variant's data is read as. The inverse
+/// of [`serialize_variant_data`].
+fn deserialize_variant(
+    name: &syn::Ident,
+    version_variant: &VersionVariant,
+    deserialize_data: proc_macro2::TokenStream,
+) -> proc_macro2::TokenStream {
+    let payload_name = version_variant.variant_ty.to_token_stream().to_string();
+    let variant = version_variant.variant_pattern(name);
+
+    match &version_variant.fields {
+        VariantFields::Newtype => version_variant.wrap_payload(name, deserialize_data),
+        VariantFields::Named(fields) => {
+            let field_names: Vec<_> = fields.iter().map(|field| &field.ident).collect();
+            let field_tys = fields.iter().map(|field| &field.ty);
+            quote! {{
+                #[derive(::pro_serde_versioned::serde::Deserialize)]
+                #[serde(crate = "::pro_serde_versioned::serde", rename = #payload_name)]
+                struct __VersionedPayload {
+                    #(#field_names: #field_tys),*
+                }
+
+                let __VersionedPayload { #(#field_names),* } = #deserialize_data;
+                #variant
+            }}
+        }
+        VariantFields::Unnamed(fields) => {
+            let field_names: Vec<_> = (0..fields.len())
+                .map(|index| format_ident!("field_{}", index))
+                .collect();
+            let field_tys = fields.iter().map(|field| &field.ty);
+            quote! {{
+                #[derive(::pro_serde_versioned::serde::Deserialize)]
+                #[serde(crate = "::pro_serde_versioned::serde", rename = #payload_name)]
+                struct __VersionedPayload(#(#field_tys),*);
+
+                let __VersionedPayload(#(#field_names),*) = #deserialize_data;
+                #variant
+            }}
+        }
+        VariantFields::Unit => quote! {{
+            let () = #deserialize_data;
+            #variant
+        }},
     }
-    .into()
 }
 
-#[proc_macro_derive(VersionedUpgrade)]
-pub fn upgradable_enum(input: TokenStream) -> TokenStream {
-    let ast: DeriveInput = syn::parse(input).unwrap();
+fn expand_versioned_upgrade(ast: &DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
     let name = &ast.ident;
-    let mut latest_variant_ty = None;
-    let version_variants = get_version_variants(&ast);
+    let VersionedEnum {
+        attrs: container_attrs,
+        variants: version_variants,
+        unknown_variant,
+    } = parse_versioned_enum(ast)?;
+    let unknown_variant = unknown_variant.map(|unknown_variant| unknown_variant.ident);
+    if let (Some(unknown_variant), false) = (&unknown_variant, container_attrs.fallible) {
+        return Err(syn::Error::new(
+            unknown_variant.span(),
+            format!(
+                "`{}` holds unknown versions, which cannot be upgraded, so the upgrade to the \
+                 latest version can fail; mark the enum `#[versioned(fallible)]`",
+                unknown_variant
+            ),
+        ));
+    }
 
-    for variant in version_variants.values() {
-        if variant.latest {
-            latest_variant_ty = Some(variant.variant_ty.clone());
-            break;
-        }
+    let latest_variant_ty = &version_variants
+        .values()
+        .find(|version_variant| version_variant.latest)
+        .expect("at least one variant")
+        .variant_ty;
+
+    let variant_versions: Vec<_> = version_variants.keys().collect();
+    let mut upgrade_match_arms = Vec::new();
+    let mut try_upgrade_match_arms = Vec::new();
+    let mut upgrade_to_version_match_arms = Vec::new();
+    for (version_variant, next_variant) in get_upgrade_steps(&version_variants)? {
+        let variant_ident = &version_variant.variant_ident;
+        let variant_ty = &version_variant.variant_ty;
+        let variant_pattern = version_variant.variant_pattern(name);
+        let payload = version_variant.payload();
+        let version_number = version_variant.version_number;
+        upgrade_to_version_match_arms.push(quote! {
+            #name::#variant_ident { .. } if version < #version_number => {
+                ::core::result::Result::Err(::pro_serde_versioned::UpgradeError::OlderVersion {
+                    current: #version_number,
+                    target: version,
+                })
+            },
+        });
+
+        let Some(next_variant) = next_variant else {
+            upgrade_match_arms.push(quote! {
+                #variant_pattern => #payload,
+            });
+            try_upgrade_match_arms.push(quote! {
+                #variant_pattern => ::core::result::Result::Ok(#payload),
+            });
+            continue;
+        };
+
+        let next_version_number = next_variant.version_number;
+        let next_variant_ty = &next_variant.variant_ty;
+        let upgraded = next_variant.wrap_payload(name, quote! { upgraded });
+        let try_upgrade_step = quote! {{
+            let upgraded =
+                <#variant_ty as ::pro_serde_versioned::TryUpgrade<#next_variant_ty>>::try_upgrade(#payload)
+                    .map_err(|source| ::pro_serde_versioned::UpgradeError::Step {
+                        from: #version_number,
+                        to: #next_version_number,
+                        source: ::core::convert::Into::into(source),
+                    })?;
+            #upgraded
+        }};
+
+        upgrade_match_arms.push(quote! {
+            #variant_pattern => {
+                let upgraded: #next_variant_ty = ::pro_serde_versioned::Upgrade::upgrade(#payload);
+                ::pro_serde_versioned::VersionedUpgrade::upgrade_to_latest(#upgraded)
+            },
+        });
+        try_upgrade_match_arms.push(quote! {
+            #variant_pattern => {
+                ::pro_serde_versioned::TryVersionedUpgrade::try_upgrade_to_latest(
+                    #try_upgrade_step
+                )
+            },
+        });
+        upgrade_to_version_match_arms.push(quote! {
+            #variant_pattern if version > #version_number => {
+                ::pro_serde_versioned::TryVersionedUpgrade::upgrade_to_version(
+                    #try_upgrade_step,
+                    version,
+                )
+            },
+        });
     }
 
-    let latest_variant_ty = match latest_variant_ty {
-        Some(latest_variant_ty) => latest_variant_ty,
-        None => panic!("No latest variant found"),
-    };
+    let version_names_const = version_names_const(&version_variants);
+
+    if let Some(unknown_variant) = &unknown_variant {
+        let undecoded = quote! {
+            #name::#unknown_variant(raw) => ::core::result::Result::Err(
+                ::pro_serde_versioned::UpgradeError::Undecoded {
+                    version: ::core::clone::Clone::clone(&raw.version),
+                }
+            ),
+        };
+        try_upgrade_match_arms.push(undecoded.clone());
+        upgrade_to_version_match_arms.push(undecoded);
+    }
 
-    let upgrade_match_arms = generate_upgrade_match_arms(&ast, version_variants);
+    let (impl_generics, ty_generics, where_clause) = ast.generics.split_for_impl();
 
-    let generics = ast.generics;
-    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+    let versioned_upgrade_impl = (!container_attrs.fallible).then(|| {
+        quote! {
+            impl #impl_generics ::pro_serde_versioned::VersionedUpgrade for #name #ty_generics #where_clause {
+                type Latest = #latest_variant_ty;
 
-    let gen = quote! {
-        impl #impl_generics ::pro_serde_versioned::VersionedUpgrade for #name #ty_generics #where_clause {
+                fn upgrade_to_latest(self) -> Self::Latest {
+                    match self {
+                        #(#upgrade_match_arms)*
+                    }
+                }
+            }
+        }
+    });
+
+    let transitive_impls = generate_transitive_impls(ast, &container_attrs, &version_variants);
+
+    Ok(quote! {
+        #versioned_upgrade_impl
+        #transitive_impls
+
+        impl #impl_generics ::pro_serde_versioned::TryVersionedUpgrade for #name #ty_generics #where_clause {
             type Latest = #latest_variant_ty;
-            fn upgrade_to_latest(self) -> Self::Latest {
+
+            #version_names_const
+
+            fn try_upgrade_to_latest(
+                self,
+            ) -> ::core::result::Result<Self::Latest, ::pro_serde_versioned::UpgradeError> {
                 match self {
-                    #(#upgrade_match_arms)*
+                    #(#try_upgrade_match_arms)*
+                }
+            }
+
+            fn upgrade_to_version(
+                self,
+                version: usize,
+            ) -> ::core::result::Result<Self, ::pro_serde_versioned::UpgradeError> {
+                if !::core::matches!(version, #(#variant_versions)|*) {
+                    return ::core::result::Result::Err(
+                        ::pro_serde_versioned::UpgradeError::UnknownVersion { version }
+                    );
+                }
+
+                match self {
+                    #(#upgrade_to_version_match_arms)*
+                    value => ::core::result::Result::Ok(value),
                 }
             }
         }
-    };
+    })
+}
+
+/// Generates the `Upgrade` and `From` impls between versions which are
+/// requested by `#[versioned(transitive)]` and `#[versioned(transitive_from)]`,
+/// each of which chains every step in between.
+fn generate_transitive_impls(
+    ast: &DeriveInput,
+    container_attrs: &ContainerAttrs,
+    version_variants: &BTreeMap<usize, VersionVariant>,
+) -> proc_macro2::TokenStream {
+    let (impl_generics, _, where_clause) = ast.generics.split_for_impl();
+    let variant_tys: Vec<_> = version_variants
+        .values()
+        .map(|version_variant| &version_variant.variant_ty)
+        .collect();
+
+    let mut impls = Vec::new();
+    for (from_index, from_ty) in variant_tys.iter().enumerate() {
+        for to_index in from_index + 1..variant_tys.len() {
+            let to_ty = variant_tys[to_index];
+            let steps = variant_tys[from_index..=to_index].windows(2).map(|step| {
+                let (step_from_ty, step_to_ty) = (step[0], step[1]);
+                quote! {
+                    let value = <#step_from_ty as ::pro_serde_versioned::Upgrade<#step_to_ty>>::upgrade(value);
+                }
+            });
+
+            let steps = quote! {
+                #(#steps)*
+                value
+            };
+
+            if container_attrs.transitive && to_index > from_index + 1 {
+                impls.push(quote! {
+                    impl #impl_generics ::pro_serde_versioned::Upgrade<#to_ty> for #from_ty #where_clause {
+                        fn upgrade(self) -> #to_ty {
+                            let value = self;
+                            #steps
+                        }
+                    }
+                });
+            }
 
-    gen.into()
+            if container_attrs.transitive_from {
+                impls.push(quote! {
+                    impl #impl_generics ::core::convert::From<#from_ty> for #to_ty #where_clause {
+                        fn from(value: #from_ty) -> #to_ty {
+                            #steps
+                        }
+                    }
+                });
+            }
+        }
+    }
+
+    quote! {
+        #(#impls)*
+    }
 }
 
-fn generate_upgrade_match_arms(
+/// Generates a payload struct for each variant which does not wrap a type of
+/// its own, e.g. `MyStructVersionV2` for `MyStructVersion::V2 { .. }`, so that
+/// the upgrade and downgrade steps have a type to be implemented on. They
+/// derive what version structures usually do, so that they can be compared
+/// once upgraded and serialized as their variant is.
+fn generate_payload_structs(
     ast: &DeriveInput,
-    version_variants: HashMap<usize, VersionVariant>,
-) -> Vec<proc_macro2::TokenStream> {
+    version_variants: &BTreeMap<usize, VersionVariant>,
+) -> proc_macro2::TokenStream {
     let name = &ast.ident;
-    let mut match_arms = Vec::new();
+    let vis = &ast.vis;
+    let payload_structs = version_variants.values().filter_map(|version_variant| {
+        let variant_ty = &version_variant.variant_ty;
+        let doc = format!(
+            "The data of [`{}::{}`], which its upgrade and downgrade steps are implemented on.",
+            name, version_variant.variant_ident
+        );
+
+        let body = match &version_variant.fields {
+            VariantFields::Newtype => return None,
+            VariantFields::Named(fields) => {
+                let field_names = fields.iter().map(|field| &field.ident);
+                let field_tys = fields.iter().map(|field| &field.ty);
+                quote! { { #(#vis #field_names: #field_tys),* } }
+            }
+            VariantFields::Unnamed(fields) => {
+                let field_tys = fields.iter().map(|field| &field.ty);
+                quote! { (#(#vis #field_tys),*); }
+            }
+            VariantFields::Unit => quote! { ; },
+        };
+
+        Some(quote! {
+            #[doc = #doc]
+            #[derive(
+                ::core::fmt::Debug,
+                ::core::clone::Clone,
+                ::core::cmp::PartialEq,
+                ::pro_serde_versioned::serde::Serialize,
+                ::pro_serde_versioned::serde::Deserialize,
+            )]
+            #[serde(crate = "::pro_serde_versioned::serde")]
+            #vis struct #variant_ty #body
+        })
+    });
+
+    quote! {
+        #(#payload_structs)*
+    }
+}
+
+/// The `VERSION_NAMES` const of the impls which take a version, listing the
+/// versions declared with `#[versioned(version = "..")]`, if there are any.
+fn version_names_const(
+    version_variants: &BTreeMap<usize, VersionVariant>,
+) -> Option<proc_macro2::TokenStream> {
+    let (named_versions, version_names): (Vec<_>, Vec<_>) = version_variants
+        .values()
+        .filter_map(|version_variant| {
+            let version_name = version_variant.version_name.as_ref()?;
+            Some((version_variant.version_number, version_name))
+        })
+        .unzip();
+
+    (!version_names.is_empty()).then(|| {
+        quote! {
+            const VERSION_NAMES: &'static [(usize, &'static str)] =
+                &[#((#named_versions, #version_names)),*];
+        }
+    })
+}
+
+fn expand_versioned_downgrade(ast: &DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
+    let name = &ast.ident;
+    let VersionedEnum {
+        variants: version_variants,
+        unknown_variant,
+        ..
+    } = parse_versioned_enum(ast)?;
+    let unknown_variant = unknown_variant.map(|unknown_variant| unknown_variant.ident);
+
+    let variant_versions: Vec<_> = version_variants.keys().collect();
+    let mut downgrade_match_arms = Vec::new();
+    let mut newer_match_arms = Vec::new();
+    for (version_variant, next_variant) in get_upgrade_steps(&version_variants)? {
+        let variant_ident = &version_variant.variant_ident;
+        let version_number = version_variant.version_number;
+        newer_match_arms.push(quote! {
+            #name::#variant_ident { .. } if version > #version_number => {
+                ::core::result::Result::Err(::pro_serde_versioned::DowngradeError::NewerVersion {
+                    current: #version_number,
+                    target: version,
+                })
+            },
+        });
+
+        let Some(next_variant) = next_variant else {
+            continue;
+        };
+
+        let next_variant_pattern = next_variant.variant_pattern(name);
+        let next_payload = next_variant.payload();
+        let next_version_number = next_variant.version_number;
+        let variant_ty = &version_variant.variant_ty;
+        let downgraded = version_variant.wrap_payload(name, quote! { downgraded });
+        downgrade_match_arms.push(quote! {
+            #next_variant_pattern if version < #next_version_number => {
+                let downgraded: #variant_ty = ::pro_serde_versioned::Downgrade::downgrade(#next_payload);
+                ::pro_serde_versioned::VersionedDowngrade::downgrade_to_version(#downgraded, version)
+            },
+        });
+    }
+
+    let version_names_const = version_names_const(&version_variants);
+
+    let undecoded_match_arm = unknown_variant.map(|unknown_variant| {
+        quote! {
+            #name::#unknown_variant(raw) => ::core::result::Result::Err(
+                ::pro_serde_versioned::DowngradeError::Undecoded {
+                    version: ::core::clone::Clone::clone(&raw.version),
+                }
+            ),
+        }
+    });
+
+    let (impl_generics, ty_generics, where_clause) = ast.generics.split_for_impl();
+
+    Ok(quote! {
+        impl #impl_generics ::pro_serde_versioned::VersionedDowngrade for #name #ty_generics #where_clause {
+            #version_names_const
+
+            fn downgrade_to_version(
+                self,
+                version: usize,
+            ) -> ::core::result::Result<Self, ::pro_serde_versioned::DowngradeError> {
+                if !::core::matches!(version, #(#variant_versions)|*) {
+                    return ::core::result::Result::Err(
+                        ::pro_serde_versioned::DowngradeError::UnknownVersion { version }
+                    );
+                }
+
+                match self {
+                    #(#downgrade_match_arms)*
+                    #(#newer_match_arms)*
+                    #undecoded_match_arm
+                    value => ::core::result::Result::Ok(value),
+                }
+            }
+        }
+    })
+}
+
+/// Pairs each variant with the variant it upgrades to, or [`None`] for the
+/// latest variant.
+fn get_upgrade_steps(
+    version_variants: &BTreeMap<usize, VersionVariant>,
+) -> syn::Result<Vec<(&VersionVariant, Option<&VersionVariant>)>> {
+    let mut upgrade_steps = Vec::new();
+    let mut errors = Errors::default();
 
     for version_variant in version_variants.values() {
         let version_number = version_variant.version_number;
         let variant_ident = &version_variant.variant_ident;
 
-        if !version_variant.latest {
-            let next_variant = version_variants
-                .get(&(version_number + 1))
-                .expect("No variant for next version");
-
-            let next_variant_ident = &next_variant.variant_ident;
-            let next_variant_ty = &next_variant.variant_ty;
-            match_arms.push(quote! {
-                #name::#variant_ident(value) => {
-                    let upgraded: #next_variant_ty = value.upgrade();
-                    #name::#next_variant_ident(upgraded).upgrade_to_latest()
-                },
-            });
+        if version_variant.latest {
+            upgrade_steps.push((version_variant, None));
+        } else if let Some(next_variant) = version_variants.get(&(version_number + 1)) {
+            upgrade_steps.push((version_variant, Some(next_variant)));
         } else {
-            match_arms.push(quote! {
-                #name::#variant_ident(value) => value,
-            });
+            errors.push(syn::Error::new(
+                variant_ident.span(),
+                format!(
+                    "no variant for version {}, which must follow `{}`; add a variant for version \
+                     {} or renumber the variants so their versions are consecutive",
+                    version_number + 1,
+                    variant_ident,
+                    version_number + 1,
+                ),
+            ));
         }
     }
 
-    match_arms
+    errors.finish()?;
+    Ok(upgrade_steps)
 }
 
-fn get_version_variants(ast: &DeriveInput) -> HashMap<usize, VersionVariant> {
-    let mut version_variants: HashMap<usize, VersionVariant> = HashMap::new();
+fn parse_versioned_enum(ast: &DeriveInput) -> syn::Result<VersionedEnum> {
+    match (get_container_attrs(&ast.attrs), get_version_variants(ast)) {
+        (Ok(attrs), Ok((variants, unknown_variant))) => {
+            check_variants(&attrs, &variants)?;
+            Ok(VersionedEnum {
+                attrs,
+                variants,
+                unknown_variant,
+            })
+        }
+        (Err(mut error), Err(variants_error)) => {
+            error.combine(variants_error);
+            Err(error)
+        }
+        (Err(error), _) | (_, Err(error)) => Err(error),
+    }
+}
+
+/// Checks the variants against the options which refer to them.
+fn check_variants(
+    container_attrs: &ContainerAttrs,
+    version_variants: &BTreeMap<usize, VersionVariant>,
+) -> syn::Result<()> {
+    let mut errors = Errors::default();
+    if let Some(Err(error)) = container_attrs.unversioned_version(version_variants) {
+        errors.push(error);
+    }
 
-    let mut max = 0;
+    if let Some(repr) = &container_attrs.repr {
+        let max = match repr.to_string().as_str() {
+            "u8" => u8::MAX.into(),
+            "u16" => u16::MAX.into(),
+            "u32" => u32::MAX.into(),
+            _ => u64::MAX,
+        };
 
-    if let Data::Enum(data_enum) = &ast.data {
-        for variant in &data_enum.variants {
-            let version_number = variant
-                .ident
-                .to_string()
-                .replace("V", "")
-                .parse::<usize>()
-                .expect("Invalid version number");
-            max = std::cmp::max(max, version_number);
-            version_variants.insert(
-                variant
+        for version_variant in version_variants
+            .values()
+            .filter(|version_variant| version_variant.version_number as u64 > max)
+        {
+            errors.push(syn::Error::new(
+                version_variant.variant_ident.span(),
+                format!(
+                    "version number {} does not fit in `{}`, the `repr` of this enum",
+                    version_variant.version_number, repr
+                ),
+            ));
+        }
+    }
+
+    // Internally tagged versions are merged with their version tag, so variants
+    // which are not wrapped in a type of their own must have named fields.
+    for version_variant in version_variants
+        .values()
+        .filter(|_| container_attrs.internally_tagged.is_some())
+    {
+        if let VariantFields::Unnamed(_) | VariantFields::Unit = version_variant.fields {
+            errors.push(syn::Error::new(
+                version_variant.variant_ident.span(),
+                format!(
+                    "variants of internally tagged enums must serialize as a map; give `{}` named \
+                     fields or wrap a struct",
+                    version_variant.variant_ident
+                ),
+            ));
+        }
+    }
+
+    errors.finish()
+}
+
+/// Reads the enum's variants, keyed by version number, and its
+/// `#[versioned(unknown)]` variant, if it has one.
+fn get_version_variants(
+    ast: &DeriveInput,
+) -> syn::Result<(BTreeMap<usize, VersionVariant>, Option<UnknownVariant>)> {
+    let mut version_variants: BTreeMap<usize, VersionVariant> = BTreeMap::new();
+    let mut unknown_variant: Option<syn::Ident> = None;
+    let mut unknown_raw_ty = None;
+    let mut errors = Errors::default();
+
+    let data_enum = match &ast.data {
+        Data::Enum(data_enum) => data_enum,
+        Data::Struct(data_struct) => {
+            return Err(syn::Error::new(
+                data_struct.struct_token.span,
+                "versioned types must be enums with one variant per version, e.g. `enum \
+                 MyStructVersion { V1(MyStructV1), V2(MyStructV2) }`",
+            ))
+        }
+        Data::Union(data_union) => {
+            return Err(syn::Error::new(
+                data_union.union_token.span,
+                "versioned types must be enums with one variant per version, e.g. `enum \
+                 MyStructVersion { V1(MyStructV1), V2(MyStructV2) }`",
+            ))
+        }
+    };
+
+    // Named versions are numbered one after the variant declared before them,
+    // so that the upgrade chain follows the declaration order.
+    let mut previous_version_number = Some(0);
+    for variant in &data_enum.variants {
+        let mut version_name = None;
+        let version_number = match get_version_attr(&variant.attrs) {
+            Ok(Some(VersionAttr::Number(version_number))) => Some(version_number),
+            Ok(Some(VersionAttr::Name(name))) => {
+                if let Some(existing) = version_variants.values().find(|version_variant| {
+                    version_variant
+                        .version_name
+                        .as_ref()
+                        .map(syn::LitStr::value)
+                        == Some(name.value())
+                }) {
+                    errors.push(syn::Error::new(
+                        name.span(),
+                        format!(
+                            "duplicate version name {:?}, already used by `{}`",
+                            name.value(),
+                            existing.variant_ident
+                        ),
+                    ));
+                }
+
+                version_name = Some(name);
+                previous_version_number.map(|version_number| version_number + 1)
+            }
+            Ok(Some(VersionAttr::Unknown)) => {
+                if let Some(existing) = &unknown_variant {
+                    errors.push(syn::Error::new(
+                        variant.ident.span(),
+                        format!(
+                            "duplicate `unknown` variant, `{}` already holds unknown versions",
+                            existing
+                        ),
+                    ));
+                } else {
+                    unknown_raw_ty = raw_envelope_data(&variant.fields);
+                    if unknown_raw_ty.is_none() {
+                        errors.push(syn::Error::new(
+                            variant.ident.span(),
+                            format!(
+                                "`unknown` variants hold the undecoded data of unknown versions; \
+                                 write `{}(RawEnvelope<R>)`, where `R` is the raw data of the \
+                                 format it is read from, e.g. `serde_json::Value`",
+                                variant.ident
+                            ),
+                        ));
+                    }
+                }
+
+                unknown_variant.get_or_insert_with(|| variant.ident.clone());
+                continue;
+            }
+            Ok(None) => {
+                let version_number = variant
                     .ident
                     .to_string()
-                    .replace("V", "")
-                    .parse::<usize>()
-                    .expect("Invalid version number"),
-                VersionVariant {
-                    version_number,
-                    variant_ident: variant.ident.clone(),
-                    variant_ty: {
-                        if variant.fields.len() != 1 {
-                            panic!("Only single-field variants are supported");
-                        }
-                        if let Fields::Unnamed(fields_unnamed) = &variant.fields {
-                            fields_unnamed.unnamed[0].ty.clone()
-                        } else {
-                            panic!("Only unnamed fields are supported");
-                        }
-                    },
-                    latest: false,
-                },
-            );
+                    .strip_prefix('V')
+                    .and_then(|version| version.parse::<usize>().ok());
+
+                if version_number.is_none() {
+                    errors.push(syn::Error::new(
+                        variant.ident.span(),
+                        format!(
+                            "cannot infer a version number from `{}`; name the variant `V<N>` \
+                             (e.g. `V1`) or add `#[versioned(version = N)]`",
+                            variant.ident
+                        ),
+                    ));
+                }
+
+                version_number
+            }
+            Err(error) => {
+                errors.push(error);
+                None
+            }
+        };
+
+        let fields = match &variant.fields {
+            Fields::Unnamed(fields_unnamed) if fields_unnamed.unnamed.len() == 1 => {
+                VariantFields::Newtype
+            }
+            Fields::Named(fields_named) => {
+                VariantFields::Named(fields_named.named.iter().cloned().collect())
+            }
+            Fields::Unnamed(fields_unnamed) => {
+                VariantFields::Unnamed(fields_unnamed.unnamed.iter().cloned().collect())
+            }
+            Fields::Unit => VariantFields::Unit,
+        };
+
+        let variant_ty = match &fields {
+            VariantFields::Newtype => Some(variant.fields.iter().next().unwrap().ty.clone()),
+            _ if !ast.generics.params.is_empty() => {
+                errors.push(syn::Error::new(
+                    variant.ident.span(),
+                    format!(
+                        "variants of generic versioned enums must wrap exactly one type; move \
+                         these fields into a struct and write `{}(MyStruct)`",
+                        variant.ident
+                    ),
+                ));
+                None
+            }
+            _ => {
+                let payload_ident = format_ident!(
+                    "{}{}",
+                    ast.ident,
+                    variant.ident,
+                    span = variant.ident.span()
+                );
+                Some(syn::parse_quote!(#payload_ident))
+            }
+        };
+
+        previous_version_number = version_number;
+        let (Some(version_number), Some(variant_ty)) = (version_number, variant_ty) else {
+            continue;
+        };
+
+        if let Some(existing) = version_variants.get(&version_number) {
+            let message = match &version_name {
+                Some(_) => format!(
+                    "duplicate version number {}, already used by `{}`; named versions are \
+                     numbered one after the variant declared before them, so declare them after \
+                     every numbered version",
+                    version_number, existing.variant_ident
+                ),
+                None => format!(
+                    "duplicate version number {}, already used by `{}`; give each variant a \
+                     distinct `#[versioned(version = N)]`",
+                    version_number, existing.variant_ident
+                ),
+            };
+
+            errors.push(syn::Error::new(variant.ident.span(), message));
+            continue;
         }
+
+        version_variants.insert(version_number, VersionVariant {
+            version_number,
+            variant_ident: variant.ident.clone(),
+            variant_ty,
+            fields,
+            version_name,
+            latest: false,
+        });
     }
-    {
-        let latest_version = version_variants.get_mut(&max).expect("No latest version");
+
+    // The `unknown` variant is not a version of its own.
+    if data_enum.variants.len() == usize::from(unknown_variant.is_some()) {
+        errors.push(syn::Error::new(
+            ast.ident.span(),
+            "versioned enums need at least one variant, e.g. `V1(MyStructV1)`",
+        ));
+    }
+
+    errors.finish()?;
+
+    if let Some((_, latest_version)) = version_variants.iter_mut().next_back() {
         latest_version.latest = true;
     }
 
-    version_variants
+    let unknown_variant = unknown_variant.map(|ident| UnknownVariant {
+        ident,
+        raw_ty: unknown_raw_ty.expect("checked unknown variant"),
+    });
+    Ok((version_variants, unknown_variant))
+}
+
+/// The `R` of an `unknown` variant holding a `RawEnvelope<R>`.
+fn raw_envelope_data(fields: &Fields) -> Option<syn::Type> {
+    let field = match fields {
+        Fields::Unnamed(fields) if fields.unnamed.len() == 1 => &fields.unnamed[0],
+        _ => return None,
+    };
+    let segment = match &field.ty {
+        syn::Type::Path(type_path) => type_path.path.segments.last()?,
+        _ => return None,
+    };
+    match &segment.arguments {
+        syn::PathArguments::AngleBracketed(arguments)
+            if segment.ident == "RawEnvelope" && arguments.args.len() == 1 =>
+        {
+            match &arguments.args[0] {
+                syn::GenericArgument::Type(raw_ty) => Some(raw_ty.clone()),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Options set on the enum itself with `#[versioned(...)]`.
+#[derive(Default)]
+struct ContainerAttrs {
+    /// Skip the `VersionedUpgrade` impl, for chains with steps that only
+    /// implement `TryUpgrade`.
+    fallible: bool,
+
+    /// Implement `Upgrade` directly between every pair of non-adjacent
+    /// versions. Opt-in, as the impls are on the version structures, which
+    /// may come from another crate or be shared with another enum.
+    transitive: bool,
+
+    /// Implement `From` between every pair of versions, from older to newer.
+    transitive_from: bool,
+
+    /// The envelope field holding the version number, if not `version_number`.
+    tag: Option<syn::LitStr>,
+
+    /// The envelope field holding the data, if not `data`.
+    content: Option<syn::LitStr>,
+
+    /// The field holding the version number, merged into the data itself
+    /// rather than wrapping it.
+    internally_tagged: Option<syn::LitStr>,
+
+    /// The version to decode data which has no envelope as, by number or name.
+    unversioned: Option<syn::Lit>,
+
+    /// The integer type the version number is written as, if not `usize`.
+    repr: Option<syn::Ident>,
+
+    /// Also implement serde's `Serialize` and `Deserialize`, writing the same
+    /// envelope as `versioned_serialize`.
+    serde: bool,
+}
+
+impl ContainerAttrs {
+    /// The version number of the `unversioned` version, if one was given.
+    fn unversioned_version(
+        &self,
+        version_variants: &BTreeMap<usize, VersionVariant>,
+    ) -> Option<syn::Result<usize>> {
+        let unversioned = self.unversioned.as_ref()?;
+        let version_number = match unversioned {
+            syn::Lit::Str(name) => version_variants
+                .values()
+                .find(|version_variant| {
+                    version_variant
+                        .version_name
+                        .as_ref()
+                        .map(syn::LitStr::value)
+                        == Some(name.value())
+                })
+                .map(|version_variant| version_variant.version_number),
+            syn::Lit::Int(lit) => lit
+                .base10_parse::<usize>()
+                .ok()
+                .filter(|version_number| version_variants.contains_key(version_number)),
+            _ => None,
+        };
+
+        Some(version_number.ok_or_else(|| {
+            syn::Error::new(
+                unversioned.span(),
+                format!(
+                    "no variant for version {} to decode unversioned data as",
+                    unversioned.to_token_stream()
+                ),
+            )
+        }))
+    }
+
+    /// The `TAG`, `CONTENT` and `VERSION_NAMES` consts of the serialize and
+    /// deserialize impls.
+    fn envelope_consts(
+        &self,
+        version_variants: &BTreeMap<usize, VersionVariant>,
+    ) -> proc_macro2::TokenStream {
+        let tag = self.tag.iter().chain(&self.internally_tagged);
+        let content = self.content.iter();
+        let version_names_const = version_names_const(version_variants);
+
+        quote! {
+            #(const TAG: &'static str = #tag;)*
+            #(const CONTENT: &'static str = #content;)*
+            #version_names_const
+        }
+    }
+
+    /// The integer type the version number is written as.
+    fn repr_ty(&self) -> proc_macro2::TokenStream {
+        match &self.repr {
+            Some(repr) => quote! { ::core::primitive::#repr },
+            None => quote! { ::core::primitive::usize },
+        }
+    }
+
+    /// The envelope type holding data of type `data_ty`. Versions written by
+    /// name need an envelope which reads `VERSION_NAMES`.
+    fn envelope_ty(
+        &self,
+        version_variants: &BTreeMap<usize, VersionVariant>,
+        data_ty: proc_macro2::TokenStream,
+    ) -> proc_macro2::TokenStream {
+        let has_version_names = version_variants
+            .values()
+            .any(|version_variant| version_variant.version_name.is_some());
+        let repr = self.repr_ty();
+        if self.internally_tagged.is_some() {
+            quote! { ::pro_serde_versioned::InternallyTaggedEnvelope<Self, #data_ty, #repr> }
+        } else if self.tag.is_some() || self.content.is_some() || has_version_names {
+            quote! { ::pro_serde_versioned::NamedEnvelope<Self, #data_ty, #repr> }
+        } else {
+            quote! { ::pro_serde_versioned::VersionedEnvelope<#data_ty, #repr> }
+        }
+    }
+}
+
+/// `generics` with every type parameter bounded by `bound`, for the serde impls
+/// of enums marked `#[versioned(serde)]`.
+/// Implements `trait_path<R>`, one of the traits which keep or write unknown
+/// versions, on the enum: with `items` for the `R` held by its `unknown`
+/// variant, so that other formats do not compile, or else for every `R`, of
+/// which it keeps and writes nothing.
+fn unknown_impl(
+    ast: &DeriveInput,
+    unknown_variant: Option<&UnknownVariant>,
+    trait_path: proc_macro2::TokenStream,
+    items: impl Fn(&syn::Ident, &syn::Type) -> proc_macro2::TokenStream,
+) -> proc_macro2::TokenStream {
+    let name = &ast.ident;
+    let (impl_generics, ty_generics, where_clause) = ast.generics.split_for_impl();
+    match unknown_variant {
+        Some(UnknownVariant { ident, raw_ty }) => {
+            let items = items(ident, raw_ty);
+            quote! {
+                impl #impl_generics #trait_path<#raw_ty> for #name #ty_generics #where_clause {
+                    #items
+                }
+            }
+        }
+        None => {
+            let mut generics = ast.generics.clone();
+            generics.params.push(syn::parse_quote!(__R));
+            let (impl_generics, ..) = generics.split_for_impl();
+            quote! {
+                impl #impl_generics #trait_path<__R> for #name #ty_generics #where_clause {}
+            }
+        }
+    }
+}
+
+fn bounded_generics(generics: &syn::Generics, bound: proc_macro2::TokenStream) -> syn::Generics {
+    let mut generics = generics.clone();
+    let type_params: Vec<_> = generics
+        .type_params()
+        .map(|type_param| type_param.ident.clone())
+        .collect();
+
+    let where_clause = generics.make_where_clause();
+    for type_param in type_params {
+        where_clause
+            .predicates
+            .push(syn::parse_quote!(#type_param: #bound));
+    }
+
+    generics
+}
+
+fn get_container_attrs(attrs: &[Attribute]) -> syn::Result<ContainerAttrs> {
+    let mut container_attrs = ContainerAttrs::default();
+    let mut transitive_span = None;
+    let mut errors = Errors::default();
+    for attr in attrs
+        .iter()
+        .filter(|attr| attr.path().is_ident("versioned"))
+    {
+        let result = attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("fallible") {
+                container_attrs.fallible = true;
+            } else if meta.path.is_ident("transitive") {
+                container_attrs.transitive = true;
+                transitive_span = Some(meta.path.span());
+            } else if meta.path.is_ident("transitive_from") {
+                container_attrs.transitive_from = true;
+                transitive_span = Some(meta.path.span());
+            } else if meta.path.is_ident("tag") {
+                container_attrs.tag = Some(parse_field_name(&meta)?);
+            } else if meta.path.is_ident("content") {
+                container_attrs.content = Some(parse_field_name(&meta)?);
+            } else if meta.path.is_ident("internally_tagged") {
+                container_attrs.internally_tagged = Some(parse_field_name(&meta)?);
+            } else if meta.path.is_ident("unversioned") {
+                container_attrs.unversioned = Some(match meta.value()?.parse()? {
+                    syn::Lit::Int(lit) => {
+                        lit.base10_parse::<usize>()?;
+                        syn::Lit::Int(lit)
+                    }
+                    syn::Lit::Str(lit) => syn::Lit::Str(lit),
+                    lit => {
+                        return Err(syn::Error::new(
+                            lit.span(),
+                            "expected the number or name of a version, e.g. `unversioned = 1`",
+                        ))
+                    }
+                });
+            } else if meta.path.is_ident("repr") {
+                let repr: syn::Ident = meta.value()?.parse()?;
+                if !["u8", "u16", "u32", "u64", "usize"].contains(&repr.to_string().as_str()) {
+                    return Err(syn::Error::new(
+                        repr.span(),
+                        "expected an unsigned integer type for the version number: one of `u8`, \
+                         `u16`, `u32`, `u64`, `usize`",
+                    ));
+                }
+
+                container_attrs.repr = Some(repr);
+            } else if meta.path.is_ident("serde") {
+                container_attrs.serde = true;
+            } else {
+                return Err(meta.error(
+                    "unknown `versioned` attribute; expected one of `fallible`, `transitive`, \
+                     `transitive_from`, `tag`, `content`, `internally_tagged`, `unversioned`, \
+                     `repr`, `serde`",
+                ));
+            }
+
+            Ok(())
+        });
+
+        if let Err(error) = result {
+            errors.push(error);
+        }
+    }
+
+    if let (true, Some(transitive_span)) = (container_attrs.fallible, transitive_span) {
+        errors.push(syn::Error::new(
+            transitive_span,
+            "transitive upgrades need every step to implement `Upgrade`, so they cannot be \
+             combined with `fallible`; remove one of the two attributes",
+        ));
+    }
+
+    if let (Some(internally_tagged), true) = (
+        &container_attrs.internally_tagged,
+        container_attrs.tag.is_some() || container_attrs.content.is_some(),
+    ) {
+        errors.push(syn::Error::new(
+            internally_tagged.span(),
+            "`internally_tagged` names the version field within the data, so it cannot be \
+             combined with `tag` or `content`",
+        ));
+    }
+
+    let tag = container_attrs.tag.as_ref().map(syn::LitStr::value);
+    let content = container_attrs.content.as_ref().map(syn::LitStr::value);
+    if tag.as_deref().unwrap_or("version_number") == content.as_deref().unwrap_or("data") {
+        let lit = container_attrs
+            .tag
+            .as_ref()
+            .or(container_attrs.content.as_ref());
+        errors.push(syn::Error::new(
+            lit.expect("a renamed field").span(),
+            "the envelope's `tag` and `content` fields must have different names",
+        ));
+    }
+
+    errors.finish()?;
+    Ok(container_attrs)
+}
+
+/// Parses the name of an envelope field, e.g. `tag = "v"`.
+fn parse_field_name(meta: &syn::meta::ParseNestedMeta) -> syn::Result<syn::LitStr> {
+    let lit: syn::LitStr = meta.value()?.parse()?;
+    if lit.value().is_empty() {
+        return Err(syn::Error::new(
+            lit.span(),
+            "envelope field names must not be empty",
+        ));
+    }
+
+    Ok(lit)
+}
+
+/// Reads an explicit version number or name from a `#[versioned(version = N)]`
+/// or `#[versioned(version = "..")]` attribute, or `#[versioned(unknown)]`, if
+/// one is present.
+fn get_version_attr(attrs: &[Attribute]) -> syn::Result<Option<VersionAttr>> {
+    let mut version = None;
+    let mut errors = Errors::default();
+    for attr in attrs
+        .iter()
+        .filter(|attr| attr.path().is_ident("versioned"))
+    {
+        let result = attr.parse_nested_meta(|meta| {
+            let version_attr = if meta.path.is_ident("version") {
+                match meta.value()?.parse()? {
+                    syn::Lit::Int(lit) => VersionAttr::Number(lit.base10_parse::<usize>()?),
+                    syn::Lit::Str(lit) if !lit.value().is_empty() => VersionAttr::Name(lit),
+                    lit => {
+                        return Err(syn::Error::new(
+                            lit.span(),
+                            "expected a version number or a non-empty version name, e.g. `version \
+                             = 2` or `version = \"2024-03\"`",
+                        ))
+                    }
+                }
+            } else if meta.path.is_ident("unknown") {
+                VersionAttr::Unknown
+            } else {
+                return Err(meta
+                    .error("unknown `versioned` attribute; expected `version = N` or `unknown`"));
+            };
+
+            if let (Some(VersionAttr::Unknown), _) | (Some(_), VersionAttr::Unknown) =
+                (&version, &version_attr)
+            {
+                return Err(meta.error(
+                    "`unknown` variants hold every version the enum does not have, so they cannot \
+                     have a `version` of their own",
+                ));
+            }
+
+            version = Some(version_attr);
+            Ok(())
+        });
+
+        if let Err(error) = result {
+            errors.push(error);
+        }
+    }
+
+    errors.finish()?;
+    Ok(version)
+}
+
+/// Accumulates errors so that every problem with an input is reported at once,
+/// rather than only the first.
+#[derive(Default)]
+struct Errors(Option<syn::Error>);
+
+impl Errors {
+    fn push(&mut self, error: syn::Error) {
+        match &mut self.0 {
+            Some(errors) => errors.combine(error),
+            None => self.0 = Some(error),
+        }
+    }
+
+    fn finish(self) -> syn::Result<()> {
+        match self.0 {
+            Some(errors) => Err(errors),
+            None => Ok(()),
+        }
+    }
 }
@@ -0,0 +1,588 @@
+use std::borrow::Cow;
+use std::fmt;
+
+use bincode::{DefaultOptions, Options};
+use serde::de::{self, DeserializeSeed, SeqAccess, Visitor};
+use serde::ser::{self, Impossible, SerializeStruct};
+use serde::{forward_to_deserialize_any, Deserialize, Serialize};
+
+use crate::{DeserializeFormat, SerializeFormat};
+
+/// The newtype name `BincodeBytes` serializes through, so that an envelope's
+/// data can be recognized and laid out as it is.
+const BINCODE_TOKEN: &str = "$pro_serde_versioned::BincodeBytes";
+
+/// An optionally-owned newtype wrapper for bytes as implemented by the
+/// [`bincode`] crate, with its default configuration. Envelopes are laid out
+/// as a fixed header of the version number, followed by the data, so that the
+/// version can be read without knowing the type of the data, which bincode
+/// could not skip over otherwise. The header is the version number as a
+/// little-endian integer of the enum's `#[versioned(repr = ..)]`, e.g. a
+/// single byte for `repr = u8`, or 8 bytes for the default `usize`; an
+/// envelope must be read with the `repr` it was written with. Version names
+/// cannot be written, as the header only holds numbers.
+///
+/// Envelopes are only laid out this way by
+/// [`versioned_serialize`](crate::VersionedSerialize::versioned_serialize);
+/// streaming them to a [`bincode::Serializer`] writes them as plain bincode.
+#[derive(Debug, PartialEq, Clone)]
+pub struct BincodeBytes<'a>(pub Cow<'a, [u8]>);
+
+impl Serialize for BincodeBytes<'_> {
+    fn serialize<S: ser::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_newtype_struct(BINCODE_TOKEN, serde_bytes::Bytes::new(&self.0))
+    }
+}
+
+impl<'de: 'a, 'a> Deserialize<'de> for BincodeBytes<'a> {
+    fn deserialize<D: de::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_newtype_struct(BINCODE_TOKEN, BincodeBytesVisitor)
+    }
+}
+
+struct BincodeBytesVisitor;
+
+impl<'de> Visitor<'de> for BincodeBytesVisitor {
+    type Value = BincodeBytes<'de>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("bincode bytes")
+    }
+
+    fn visit_borrowed_bytes<E: de::Error>(self, v: &'de [u8]) -> Result<Self::Value, E> {
+        Ok(BincodeBytes(Cow::Borrowed(v)))
+    }
+
+    fn visit_bytes<E: de::Error>(self, v: &[u8]) -> Result<Self::Value, E> {
+        Ok(BincodeBytes(Cow::Owned(v.to_vec())))
+    }
+
+    fn visit_byte_buf<E: de::Error>(self, v: Vec<u8>) -> Result<Self::Value, E> {
+        Ok(BincodeBytes(Cow::Owned(v)))
+    }
+
+    /// Any other deserializer holds the bytes as a byte string.
+    fn visit_newtype_struct<D: de::Deserializer<'de>>(
+        self,
+        deserializer: D,
+    ) -> Result<Self::Value, D::Error> {
+        let bytes: Cow<'de, [u8]> = serde_bytes::deserialize(deserializer)?;
+        Ok(BincodeBytes(bytes))
+    }
+}
+
+impl SerializeFormat for BincodeBytes<'_> {
+    type Error = bincode::Error;
+    type Raw = BincodeBytes<'static>;
+
+    fn serialize_format<T: Serialize>(data: T) -> Result<Self, Self::Error> {
+        let mut output = Vec::new();
+        match data.serialize(EnvelopeSerializer {
+            output: &mut output,
+        }) {
+            Ok(()) => Ok(BincodeBytes(Cow::Owned(output))),
+            Err(LayoutError::NotLaidOut) => {
+                Ok(BincodeBytes(Cow::Owned(bincode::serialize(&data)?)))
+            }
+            Err(LayoutError::Bincode(error)) => Err(error),
+        }
+    }
+
+    fn from_raw(raw: &BincodeBytes<'static>) -> Self {
+        BincodeBytes(Cow::Owned(raw.0.to_vec()))
+    }
+}
+
+impl<'a> DeserializeFormat for BincodeBytes<'a> {
+    type Error = bincode::Error;
+    type Raw = BincodeBytes<'static>;
+
+    fn deserialize_format<'b, T: Deserialize<'b>>(&'b self) -> Result<T, Self::Error> {
+        T::deserialize(HeaderDeserializer {
+            input: &self.0,
+            envelope: true,
+        })
+    }
+
+    fn to_raw(&self) -> BincodeBytes<'static> {
+        BincodeBytes(Cow::Owned(self.0.to_vec()))
+    }
+}
+
+/// The options [`bincode::serialize`] and [`bincode::deserialize`] use.
+fn options() -> impl Options {
+    DefaultOptions::new()
+        .with_fixint_encoding()
+        .allow_trailing_bytes()
+}
+
+/// The error of the serializers which lay envelopes out by hand.
+#[derive(Debug)]
+enum LayoutError {
+    /// The value is not laid out by hand, and is written as plain bincode
+    /// instead.
+    NotLaidOut,
+    Bincode(bincode::Error),
+}
+
+impl From<bincode::Error> for LayoutError {
+    fn from(error: bincode::Error) -> Self {
+        LayoutError::Bincode(error)
+    }
+}
+
+impl fmt::Display for LayoutError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LayoutError::NotLaidOut => f.write_str("value is not laid out by hand"),
+            LayoutError::Bincode(error) => error.fmt(f),
+        }
+    }
+}
+
+impl ser::StdError for LayoutError {}
+
+impl ser::Error for LayoutError {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        LayoutError::Bincode(ser::Error::custom(msg))
+    }
+}
+
+macro_rules! reject {
+    ($error:expr => $($method:ident $(<$generic:ident>)? ($($ty:ty),*) -> $ok:ty;)*) => {$(
+        fn $method$(<$generic: ?Sized + Serialize>)?(self, $(_: $ty),*) -> Result<$ok, Self::Error> {
+            Err($error)
+        }
+    )*};
+}
+
+/// Writes the header and data of an envelope, rejecting anything else.
+struct EnvelopeSerializer<'a> {
+    output: &'a mut Vec<u8>,
+}
+
+impl<'a> ser::Serializer for EnvelopeSerializer<'a> {
+    type Error = LayoutError;
+    type Ok = ();
+    type SerializeMap = Impossible<(), LayoutError>;
+    type SerializeSeq = Impossible<(), LayoutError>;
+    type SerializeStruct = HeaderFields<'a>;
+    type SerializeStructVariant = Impossible<(), LayoutError>;
+    type SerializeTuple = Impossible<(), LayoutError>;
+    type SerializeTupleStruct = Impossible<(), LayoutError>;
+    type SerializeTupleVariant = Impossible<(), LayoutError>;
+
+    reject! { LayoutError::NotLaidOut =>
+        serialize_bool(bool) -> ();
+        serialize_i8(i8) -> ();
+        serialize_i16(i16) -> ();
+        serialize_i32(i32) -> ();
+        serialize_i64(i64) -> ();
+        serialize_u8(u8) -> ();
+        serialize_u16(u16) -> ();
+        serialize_u32(u32) -> ();
+        serialize_u64(u64) -> ();
+        serialize_f32(f32) -> ();
+        serialize_f64(f64) -> ();
+        serialize_char(char) -> ();
+        serialize_str(&str) -> ();
+        serialize_bytes(&[u8]) -> ();
+        serialize_none() -> ();
+        serialize_some<T>(&T) -> ();
+        serialize_unit() -> ();
+        serialize_unit_struct(&'static str) -> ();
+        serialize_unit_variant(&'static str, u32, &'static str) -> ();
+        serialize_newtype_struct<T>(&'static str, &T) -> ();
+        serialize_newtype_variant<T>(&'static str, u32, &'static str, &T) -> ();
+        serialize_seq(Option<usize>) -> Self::SerializeSeq;
+        serialize_tuple(usize) -> Self::SerializeTuple;
+        serialize_tuple_struct(&'static str, usize) -> Self::SerializeTupleStruct;
+        serialize_tuple_variant(&'static str, u32, &'static str, usize) -> Self::SerializeTupleVariant;
+        serialize_map(Option<usize>) -> Self::SerializeMap;
+        serialize_struct_variant(&'static str, u32, &'static str, usize) -> Self::SerializeStructVariant;
+    }
+
+    fn serialize_struct(
+        self,
+        name: &'static str,
+        len: usize,
+    ) -> Result<Self::SerializeStruct, Self::Error> {
+        if name != "VersionedEnvelope" || len != 2 {
+            return Err(LayoutError::NotLaidOut);
+        }
+
+        Ok(HeaderFields {
+            output: self.output,
+            field: 0,
+        })
+    }
+
+    fn is_human_readable(&self) -> bool {
+        false
+    }
+}
+
+/// The version number and then the data of an envelope.
+struct HeaderFields<'a> {
+    output: &'a mut Vec<u8>,
+    field: usize,
+}
+
+impl SerializeStruct for HeaderFields<'_> {
+    type Error = LayoutError;
+    type Ok = ();
+
+    fn serialize_field<T: ?Sized + Serialize>(
+        &mut self,
+        _key: &'static str,
+        value: &T,
+    ) -> Result<(), Self::Error> {
+        if self.field == 0 {
+            value.serialize(VersionSerializer {
+                output: self.output,
+            })?;
+        } else {
+            match value.serialize(DataSerializer {
+                output: self.output,
+                raw: false,
+            }) {
+                Err(LayoutError::NotLaidOut) => bincode::serialize_into(&mut *self.output, value)?,
+                result => result?,
+            }
+        }
+
+        self.field += 1;
+        Ok(())
+    }
+
+    fn end(self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+/// Writes the version number of an envelope as the header, in the width of
+/// its integer type.
+struct VersionSerializer<'a> {
+    output: &'a mut Vec<u8>,
+}
+
+impl ser::Serializer for VersionSerializer<'_> {
+    type Error = bincode::Error;
+    type Ok = ();
+    type SerializeMap = Impossible<(), bincode::Error>;
+    type SerializeSeq = Impossible<(), bincode::Error>;
+    type SerializeStruct = Impossible<(), bincode::Error>;
+    type SerializeStructVariant = Impossible<(), bincode::Error>;
+    type SerializeTuple = Impossible<(), bincode::Error>;
+    type SerializeTupleStruct = Impossible<(), bincode::Error>;
+    type SerializeTupleVariant = Impossible<(), bincode::Error>;
+
+    reject! { ser::Error::custom("a `BincodeBytes` envelope holds a version number, not a name") =>
+        serialize_bool(bool) -> ();
+        serialize_i8(i8) -> ();
+        serialize_i16(i16) -> ();
+        serialize_i32(i32) -> ();
+        serialize_i64(i64) -> ();
+        serialize_f32(f32) -> ();
+        serialize_f64(f64) -> ();
+        serialize_char(char) -> ();
+        serialize_str(&str) -> ();
+        serialize_bytes(&[u8]) -> ();
+        serialize_none() -> ();
+        serialize_some<T>(&T) -> ();
+        serialize_unit() -> ();
+        serialize_unit_struct(&'static str) -> ();
+        serialize_unit_variant(&'static str, u32, &'static str) -> ();
+        serialize_newtype_struct<T>(&'static str, &T) -> ();
+        serialize_newtype_variant<T>(&'static str, u32, &'static str, &T) -> ();
+        serialize_seq(Option<usize>) -> Self::SerializeSeq;
+        serialize_tuple(usize) -> Self::SerializeTuple;
+        serialize_tuple_struct(&'static str, usize) -> Self::SerializeTupleStruct;
+        serialize_tuple_variant(&'static str, u32, &'static str, usize) -> Self::SerializeTupleVariant;
+        serialize_map(Option<usize>) -> Self::SerializeMap;
+        serialize_struct(&'static str, usize) -> Self::SerializeStruct;
+        serialize_struct_variant(&'static str, u32, &'static str, usize) -> Self::SerializeStructVariant;
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<(), Self::Error> {
+        self.output.extend_from_slice(&v.to_le_bytes());
+        Ok(())
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<(), Self::Error> {
+        self.output.extend_from_slice(&v.to_le_bytes());
+        Ok(())
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<(), Self::Error> {
+        self.output.extend_from_slice(&v.to_le_bytes());
+        Ok(())
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<(), Self::Error> {
+        self.output.extend_from_slice(&v.to_le_bytes());
+        Ok(())
+    }
+
+    fn is_human_readable(&self) -> bool {
+        false
+    }
+}
+
+/// Writes the bytes of a `BincodeBytes` as they are, rejecting anything
+/// else.
+struct DataSerializer<'a> {
+    output: &'a mut Vec<u8>,
+    raw: bool,
+}
+
+impl ser::Serializer for DataSerializer<'_> {
+    type Error = LayoutError;
+    type Ok = ();
+    type SerializeMap = Impossible<(), LayoutError>;
+    type SerializeSeq = Impossible<(), LayoutError>;
+    type SerializeStruct = Impossible<(), LayoutError>;
+    type SerializeStructVariant = Impossible<(), LayoutError>;
+    type SerializeTuple = Impossible<(), LayoutError>;
+    type SerializeTupleStruct = Impossible<(), LayoutError>;
+    type SerializeTupleVariant = Impossible<(), LayoutError>;
+
+    reject! { LayoutError::NotLaidOut =>
+        serialize_bool(bool) -> ();
+        serialize_i8(i8) -> ();
+        serialize_i16(i16) -> ();
+        serialize_i32(i32) -> ();
+        serialize_i64(i64) -> ();
+        serialize_u8(u8) -> ();
+        serialize_u16(u16) -> ();
+        serialize_u32(u32) -> ();
+        serialize_u64(u64) -> ();
+        serialize_f32(f32) -> ();
+        serialize_f64(f64) -> ();
+        serialize_char(char) -> ();
+        serialize_str(&str) -> ();
+        serialize_none() -> ();
+        serialize_some<T>(&T) -> ();
+        serialize_unit() -> ();
+        serialize_unit_struct(&'static str) -> ();
+        serialize_unit_variant(&'static str, u32, &'static str) -> ();
+        serialize_newtype_variant<T>(&'static str, u32, &'static str, &T) -> ();
+        serialize_seq(Option<usize>) -> Self::SerializeSeq;
+        serialize_tuple(usize) -> Self::SerializeTuple;
+        serialize_tuple_struct(&'static str, usize) -> Self::SerializeTupleStruct;
+        serialize_tuple_variant(&'static str, u32, &'static str, usize) -> Self::SerializeTupleVariant;
+        serialize_map(Option<usize>) -> Self::SerializeMap;
+        serialize_struct(&'static str, usize) -> Self::SerializeStruct;
+        serialize_struct_variant(&'static str, u32, &'static str, usize) -> Self::SerializeStructVariant;
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<(), Self::Error> {
+        if !self.raw {
+            return Err(LayoutError::NotLaidOut);
+        }
+
+        self.output.extend_from_slice(v);
+        Ok(())
+    }
+
+    fn serialize_newtype_struct<T: ?Sized + Serialize>(
+        self,
+        name: &'static str,
+        value: &T,
+    ) -> Result<(), Self::Error> {
+        if self.raw || name != BINCODE_TOKEN {
+            return Err(LayoutError::NotLaidOut);
+        }
+
+        value.serialize(DataSerializer {
+            output: self.output,
+            raw: true,
+        })
+    }
+
+    fn is_human_readable(&self) -> bool {
+        false
+    }
+}
+
+/// Deserializes with [`bincode`], except that an envelope's version number is
+/// read from its header, and its data is borrowed as a `BincodeBytes` as it
+/// is. Only the top level is read as an envelope, so data is left to
+/// `bincode` as a whole.
+struct HeaderDeserializer<'de> {
+    input: &'de [u8],
+    envelope: bool,
+}
+
+macro_rules! forward_to_bincode {
+    ($($method:ident($($arg:ident: $ty:ty),*);)*) => {$(
+        fn $method<V: Visitor<'de>>(self, $($arg: $ty,)* visitor: V) -> Result<V::Value, Self::Error> {
+            (&mut bincode::Deserializer::from_slice(self.input, options())).$method($($arg,)* visitor)
+        }
+    )*};
+}
+
+impl<'de> de::Deserializer<'de> for HeaderDeserializer<'de> {
+    type Error = bincode::Error;
+
+    forward_to_bincode! {
+        deserialize_any();
+        deserialize_bool();
+        deserialize_i8();
+        deserialize_i16();
+        deserialize_i32();
+        deserialize_i64();
+        deserialize_u8();
+        deserialize_u16();
+        deserialize_u32();
+        deserialize_u64();
+        deserialize_f32();
+        deserialize_f64();
+        deserialize_char();
+        deserialize_str();
+        deserialize_string();
+        deserialize_bytes();
+        deserialize_byte_buf();
+        deserialize_option();
+        deserialize_unit();
+        deserialize_unit_struct(name: &'static str);
+        deserialize_seq();
+        deserialize_tuple(len: usize);
+        deserialize_tuple_struct(name: &'static str, len: usize);
+        deserialize_map();
+        deserialize_enum(name: &'static str, variants: &'static [&'static str]);
+        deserialize_identifier();
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        if name != BINCODE_TOKEN {
+            return (&mut bincode::Deserializer::from_slice(self.input, options()))
+                .deserialize_newtype_struct(name, visitor);
+        }
+
+        visitor.visit_borrowed_bytes(self.input)
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        if !self.envelope || name != "VersionedEnvelope" || fields.len() != 2 {
+            return (&mut bincode::Deserializer::from_slice(self.input, options()))
+                .deserialize_struct(name, fields, visitor);
+        }
+
+        visitor.visit_seq(HeaderFieldsAccess {
+            input: self.input,
+            field: 0,
+        })
+    }
+
+    /// Skips the value without parsing it, so that peeking at the version
+    /// costs nothing for the data.
+    fn deserialize_ignored_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        visitor.visit_unit()
+    }
+
+    fn is_human_readable(&self) -> bool {
+        false
+    }
+}
+
+/// The version number from the header of an envelope, and then its data.
+struct HeaderFieldsAccess<'de> {
+    input: &'de [u8],
+    field: usize,
+}
+
+impl<'de> SeqAccess<'de> for HeaderFieldsAccess<'de> {
+    type Error = bincode::Error;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> Result<Option<T::Value>, Self::Error> {
+        self.field += 1;
+        match self.field {
+            1 => seed
+                .deserialize(VersionDeserializer {
+                    input: &mut self.input,
+                })
+                .map(Some),
+            2 => seed
+                .deserialize(HeaderDeserializer {
+                    input: self.input,
+                    envelope: false,
+                })
+                .map(Some),
+            _ => Ok(None),
+        }
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(2 - self.field.min(2))
+    }
+}
+
+/// Reads the header of an envelope, in the width of the integer type the
+/// version number is read as.
+struct VersionDeserializer<'a, 'de> {
+    input: &'a mut &'de [u8],
+}
+
+impl VersionDeserializer<'_, '_> {
+    fn read<const N: usize>(self) -> Result<[u8; N], bincode::Error> {
+        if self.input.len() < N {
+            return Err(de::Error::custom(
+                "a `BincodeBytes` envelope is shorter than its header",
+            ));
+        }
+
+        let (header, data) = self.input.split_at(N);
+        *self.input = data;
+        let mut bytes = [0; N];
+        bytes.copy_from_slice(header);
+        Ok(bytes)
+    }
+}
+
+impl<'de> de::Deserializer<'de> for VersionDeserializer<'_, 'de> {
+    type Error = bincode::Error;
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u128 f32 f64 char str string bytes byte_buf option unit
+        unit_struct newtype_struct seq tuple tuple_struct map struct enum identifier ignored_any
+    }
+
+    fn deserialize_any<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Self::Error> {
+        Err(de::Error::custom(
+            "the header of a `BincodeBytes` envelope is read as an unsigned integer",
+        ))
+    }
+
+    fn deserialize_u8<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        visitor.visit_u8(u8::from_le_bytes(self.read()?))
+    }
+
+    fn deserialize_u16<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        visitor.visit_u16(u16::from_le_bytes(self.read()?))
+    }
+
+    fn deserialize_u32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        visitor.visit_u32(u32::from_le_bytes(self.read()?))
+    }
+
+    fn deserialize_u64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        visitor.visit_u64(u64::from_le_bytes(self.read()?))
+    }
+
+    fn is_human_readable(&self) -> bool {
+        false
+    }
+}
@@ -0,0 +1,524 @@
+use alloc::borrow::ToOwned;
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+use core::marker::PhantomData;
+
+use serde::de::value::{MapDeserializer, SeqDeserializer};
+use serde::de::{
+    self, DeserializeSeed, EnumAccess, IntoDeserializer, MapAccess, SeqAccess, VariantAccess,
+    Visitor,
+};
+use serde::{forward_to_deserialize_any, Deserialize, Deserializer};
+
+use crate::DeserializeFormat;
+
+/// Any value of the serde data model, buffered when it is read before the
+/// version number which decides what it deserializes as (e.g. the data of an
+/// envelope whose keys were sorted, as `serde_json::Value` sorts them).
+#[derive(Clone)]
+pub(crate) enum Content {
+    Bool(bool),
+    U64(u64),
+    I64(i64),
+    F64(f64),
+    Char(char),
+    String(String),
+    Bytes(Vec<u8>),
+    None,
+    Some(Box<Content>),
+    Unit,
+    Newtype(Box<Content>),
+    Seq(Vec<Content>),
+    Map(Vec<(Content, Content)>),
+}
+
+impl Content {
+    pub(crate) fn is_str(&self, value: &str) -> bool {
+        match self {
+            Content::String(string) => string == value,
+            Content::Bytes(bytes) => bytes == value.as_bytes(),
+            _ => false,
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Content {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(ContentVisitor)
+    }
+}
+
+struct ContentVisitor;
+
+impl<'de> Visitor<'de> for ContentVisitor {
+    type Value = Content;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("any value")
+    }
+
+    fn visit_bool<E: de::Error>(self, value: bool) -> Result<Content, E> {
+        Ok(Content::Bool(value))
+    }
+
+    fn visit_i64<E: de::Error>(self, value: i64) -> Result<Content, E> {
+        Ok(Content::I64(value))
+    }
+
+    fn visit_u64<E: de::Error>(self, value: u64) -> Result<Content, E> {
+        Ok(Content::U64(value))
+    }
+
+    fn visit_f64<E: de::Error>(self, value: f64) -> Result<Content, E> {
+        Ok(Content::F64(value))
+    }
+
+    fn visit_char<E: de::Error>(self, value: char) -> Result<Content, E> {
+        Ok(Content::Char(value))
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<Content, E> {
+        Ok(Content::String(value.to_owned()))
+    }
+
+    fn visit_string<E: de::Error>(self, value: String) -> Result<Content, E> {
+        Ok(Content::String(value))
+    }
+
+    fn visit_bytes<E: de::Error>(self, value: &[u8]) -> Result<Content, E> {
+        Ok(Content::Bytes(value.to_owned()))
+    }
+
+    fn visit_byte_buf<E: de::Error>(self, value: Vec<u8>) -> Result<Content, E> {
+        Ok(Content::Bytes(value))
+    }
+
+    fn visit_none<E: de::Error>(self) -> Result<Content, E> {
+        Ok(Content::None)
+    }
+
+    fn visit_some<D: Deserializer<'de>>(self, deserializer: D) -> Result<Content, D::Error> {
+        Ok(Content::Some(Box::new(Content::deserialize(deserializer)?)))
+    }
+
+    fn visit_unit<E: de::Error>(self) -> Result<Content, E> {
+        Ok(Content::Unit)
+    }
+
+    fn visit_newtype_struct<D: Deserializer<'de>>(
+        self,
+        deserializer: D,
+    ) -> Result<Content, D::Error> {
+        Ok(Content::Newtype(Box::new(Content::deserialize(
+            deserializer,
+        )?)))
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Content, A::Error> {
+        let mut elements = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+        while let Some(element) = seq.next_element()? {
+            elements.push(element);
+        }
+
+        Ok(Content::Seq(elements))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Content, A::Error> {
+        let mut entries = Vec::with_capacity(map.size_hint().unwrap_or(0));
+        while let Some(entry) = map.next_entry()? {
+            entries.push(entry);
+        }
+
+        Ok(Content::Map(entries))
+    }
+}
+
+/// Deserializes buffered [`Content`], as the deserializer it was read from
+/// would have.
+pub(crate) struct ContentDeserializer<E> {
+    content: Content,
+    human_readable: bool,
+    error: PhantomData<E>,
+}
+
+impl<E> ContentDeserializer<E> {
+    pub(crate) fn new(content: Content, human_readable: bool) -> Self {
+        ContentDeserializer {
+            content,
+            human_readable,
+            error: PhantomData,
+        }
+    }
+}
+
+impl<'de, E: de::Error> IntoDeserializer<'de, E> for ContentDeserializer<E> {
+    type Deserializer = Self;
+
+    fn into_deserializer(self) -> Self {
+        self
+    }
+}
+
+impl<'de, E: de::Error> Deserializer<'de> for ContentDeserializer<E> {
+    type Error = E;
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf unit unit_struct seq tuple tuple_struct map struct
+        identifier ignored_any
+    }
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, E> {
+        let human_readable = self.human_readable;
+        match self.content {
+            Content::Bool(value) => visitor.visit_bool(value),
+            Content::U64(value) => visitor.visit_u64(value),
+            Content::I64(value) => visitor.visit_i64(value),
+            Content::F64(value) => visitor.visit_f64(value),
+            Content::Char(value) => visitor.visit_char(value),
+            Content::String(value) => visitor.visit_string(value),
+            Content::Bytes(value) => visitor.visit_byte_buf(value),
+            Content::None => visitor.visit_none(),
+            Content::Some(value) => {
+                visitor.visit_some(ContentDeserializer::new(*value, human_readable))
+            }
+            Content::Unit => visitor.visit_unit(),
+            Content::Newtype(value) => {
+                visitor.visit_newtype_struct(ContentDeserializer::new(*value, human_readable))
+            }
+            Content::Seq(elements) => {
+                let mut seq = SeqDeserializer::new(
+                    elements
+                        .into_iter()
+                        .map(|element| ContentDeserializer::new(element, human_readable)),
+                );
+                let value = visitor.visit_seq(&mut seq)?;
+                seq.end()?;
+                Ok(value)
+            }
+            Content::Map(entries) => {
+                let mut map = MapDeserializer::new(entries.into_iter().map(|(key, value)| {
+                    (
+                        ContentDeserializer::new(key, human_readable),
+                        ContentDeserializer::new(value, human_readable),
+                    )
+                }));
+                let value = visitor.visit_map(&mut map)?;
+                map.end()?;
+                Ok(value)
+            }
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, E> {
+        match self.content {
+            Content::None | Content::Unit => visitor.visit_none(),
+            Content::Some(value) => {
+                visitor.visit_some(ContentDeserializer::new(*value, self.human_readable))
+            }
+            _ => visitor.visit_some(self),
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, E> {
+        match self.content {
+            Content::Newtype(value) => {
+                visitor.visit_newtype_struct(ContentDeserializer::new(*value, self.human_readable))
+            }
+            _ => visitor.visit_newtype_struct(self),
+        }
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, E> {
+        match self.content {
+            Content::String(variant) => {
+                visitor.visit_enum(IntoDeserializer::<E>::into_deserializer(variant))
+            }
+            Content::Map(mut entries) if entries.len() == 1 => {
+                let (variant, value) = entries.pop().expect("one entry");
+                visitor.visit_enum(ContentEnum {
+                    variant: ContentDeserializer::new(variant, self.human_readable),
+                    value: ContentDeserializer::new(value, self.human_readable),
+                })
+            }
+            _ => Err(de::Error::custom(
+                "expected an enum variant name, or a map from the name to its data",
+            )),
+        }
+    }
+
+    fn is_human_readable(&self) -> bool {
+        self.human_readable
+    }
+}
+
+/// An enum variant buffered as a map from its name to its data.
+struct ContentEnum<E> {
+    variant: ContentDeserializer<E>,
+    value: ContentDeserializer<E>,
+}
+
+impl<'de, E: de::Error> EnumAccess<'de> for ContentEnum<E> {
+    type Error = E;
+    type Variant = ContentDeserializer<E>;
+
+    fn variant_seed<V: DeserializeSeed<'de>>(
+        self,
+        seed: V,
+    ) -> Result<(V::Value, Self::Variant), E> {
+        Ok((seed.deserialize(self.variant)?, self.value))
+    }
+}
+
+impl<'de, E: de::Error> VariantAccess<'de> for ContentDeserializer<E> {
+    type Error = E;
+
+    fn unit_variant(self) -> Result<(), E> {
+        match self.content {
+            Content::Unit | Content::None => Ok(()),
+            _ => Err(de::Error::invalid_type(
+                de::Unexpected::Map,
+                &"a unit variant",
+            )),
+        }
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(self, seed: T) -> Result<T::Value, E> {
+        seed.deserialize(self)
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, _len: usize, visitor: V) -> Result<V::Value, E> {
+        self.deserialize_any(visitor)
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, E> {
+        self.deserialize_any(visitor)
+    }
+}
+
+/// Deserializes buffered [`Content`] by reference, as
+/// [`ContentDeserializer`] does, borrowing strings and bytes from it.
+pub(crate) struct ContentRefDeserializer<'de, E> {
+    content: &'de Content,
+    human_readable: bool,
+    error: PhantomData<E>,
+}
+
+impl<'de, E> ContentRefDeserializer<'de, E> {
+    pub(crate) fn new(content: &'de Content, human_readable: bool) -> Self {
+        ContentRefDeserializer {
+            content,
+            human_readable,
+            error: PhantomData,
+        }
+    }
+}
+
+impl<'de, E: de::Error> IntoDeserializer<'de, E> for ContentRefDeserializer<'de, E> {
+    type Deserializer = Self;
+
+    fn into_deserializer(self) -> Self {
+        self
+    }
+}
+
+impl<'de, E: de::Error> Deserializer<'de> for ContentRefDeserializer<'de, E> {
+    type Error = E;
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf unit unit_struct seq tuple tuple_struct map struct
+        identifier ignored_any
+    }
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, E> {
+        let human_readable = self.human_readable;
+        match self.content {
+            Content::Bool(value) => visitor.visit_bool(*value),
+            Content::U64(value) => visitor.visit_u64(*value),
+            Content::I64(value) => visitor.visit_i64(*value),
+            Content::F64(value) => visitor.visit_f64(*value),
+            Content::Char(value) => visitor.visit_char(*value),
+            Content::String(value) => visitor.visit_borrowed_str(value),
+            Content::Bytes(value) => visitor.visit_borrowed_bytes(value),
+            Content::None => visitor.visit_none(),
+            Content::Some(value) => {
+                visitor.visit_some(ContentRefDeserializer::new(value, human_readable))
+            }
+            Content::Unit => visitor.visit_unit(),
+            Content::Newtype(value) => {
+                visitor.visit_newtype_struct(ContentRefDeserializer::new(value, human_readable))
+            }
+            Content::Seq(elements) => {
+                let mut seq = SeqDeserializer::new(
+                    elements
+                        .iter()
+                        .map(|element| ContentRefDeserializer::new(element, human_readable)),
+                );
+                let value = visitor.visit_seq(&mut seq)?;
+                seq.end()?;
+                Ok(value)
+            }
+            Content::Map(entries) => {
+                let mut map = MapDeserializer::new(entries.iter().map(|(key, value)| {
+                    (
+                        ContentRefDeserializer::new(key, human_readable),
+                        ContentRefDeserializer::new(value, human_readable),
+                    )
+                }));
+                let value = visitor.visit_map(&mut map)?;
+                map.end()?;
+                Ok(value)
+            }
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, E> {
+        match self.content {
+            Content::None | Content::Unit => visitor.visit_none(),
+            Content::Some(value) => {
+                visitor.visit_some(ContentRefDeserializer::new(value, self.human_readable))
+            }
+            _ => visitor.visit_some(self),
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, E> {
+        match self.content {
+            Content::Newtype(value) => visitor
+                .visit_newtype_struct(ContentRefDeserializer::new(value, self.human_readable)),
+            _ => visitor.visit_newtype_struct(self),
+        }
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, E> {
+        match self.content {
+            Content::String(variant) => {
+                visitor.visit_enum(IntoDeserializer::<E>::into_deserializer(variant.as_str()))
+            }
+            Content::Map(entries) if entries.len() == 1 => {
+                let (variant, value) = &entries[0];
+                visitor.visit_enum(ContentRefEnum {
+                    variant: ContentRefDeserializer::new(variant, self.human_readable),
+                    value: ContentRefDeserializer::new(value, self.human_readable),
+                })
+            }
+            _ => Err(de::Error::custom(
+                "expected an enum variant name, or a map from the name to its data",
+            )),
+        }
+    }
+
+    fn is_human_readable(&self) -> bool {
+        self.human_readable
+    }
+}
+
+/// An enum variant buffered as a map from its name to its data, deserialized
+/// by reference.
+struct ContentRefEnum<'de, E> {
+    variant: ContentRefDeserializer<'de, E>,
+    value: ContentRefDeserializer<'de, E>,
+}
+
+impl<'de, E: de::Error> EnumAccess<'de> for ContentRefEnum<'de, E> {
+    type Error = E;
+    type Variant = ContentRefDeserializer<'de, E>;
+
+    fn variant_seed<V: DeserializeSeed<'de>>(
+        self,
+        seed: V,
+    ) -> Result<(V::Value, Self::Variant), E> {
+        Ok((seed.deserialize(self.variant)?, self.value))
+    }
+}
+
+impl<'de, E: de::Error> VariantAccess<'de> for ContentRefDeserializer<'de, E> {
+    type Error = E;
+
+    fn unit_variant(self) -> Result<(), E> {
+        match self.content {
+            Content::Unit | Content::None => Ok(()),
+            _ => Err(de::Error::invalid_type(
+                de::Unexpected::Map,
+                &"a unit variant",
+            )),
+        }
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(self, seed: T) -> Result<T::Value, E> {
+        seed.deserialize(self)
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, _len: usize, visitor: V) -> Result<V::Value, E> {
+        self.deserialize_any(visitor)
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, E> {
+        self.deserialize_any(visitor)
+    }
+}
+
+/// Any deserialized value, used as the format of a versioned enum read from a
+/// serde deserializer rather than from a [`DeserializeFormat`] of its own.
+pub(crate) struct BufferedFormat {
+    content: Content,
+    human_readable: bool,
+}
+
+impl<'de> Deserialize<'de> for BufferedFormat {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let human_readable = deserializer.is_human_readable();
+        Ok(BufferedFormat {
+            content: Content::deserialize(deserializer)?,
+            human_readable,
+        })
+    }
+}
+
+impl DeserializeFormat for BufferedFormat {
+    type Error = de::value::Error;
+    // Unknown versions cannot be kept from a deserializer, whose format is
+    // not known.
+    type Raw = ();
+
+    fn deserialize_format<'a, T>(&'a self) -> Result<T, Self::Error>
+    where
+        T: Deserialize<'a>,
+    {
+        T::deserialize(ContentRefDeserializer::new(
+            &self.content,
+            self.human_readable,
+        ))
+    }
+
+    fn to_raw(&self) {}
+}
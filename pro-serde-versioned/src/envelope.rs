@@ -0,0 +1,753 @@
+use alloc::borrow::ToOwned;
+use alloc::string::String;
+use core::fmt;
+use core::marker::PhantomData;
+
+use serde::de::{
+    self, DeserializeOwned, DeserializeSeed, IgnoredAny, MapAccess, SeqAccess, Unexpected, Visitor,
+};
+use serde::ser::{self, SerializeStruct};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::content::{Content, ContentDeserializer};
+use crate::{
+    DeserializeFormat, DeserializeVersion, RawVersion, VersionedDeserialize, VersionedEnvelope,
+    VersionedError, VersionedSerialize,
+};
+
+/// An envelope holding a version number and the data serialized with it, which
+/// can be built without knowing its layout.
+pub trait Envelope<T>: Sized {
+    /// Builds the envelope, or `None` if `version_number` does not fit in the
+    /// envelope's [`VersionNumber`] type.
+    fn try_from_parts(version_number: usize, data: T) -> Option<Self>;
+
+    /// Builds the envelope. Panics if `version_number` does not fit in the
+    /// envelope's [`VersionNumber`] type, which the derives check for every
+    /// version of an enum.
+    fn from_parts(version_number: usize, data: T) -> Self {
+        Self::try_from_parts(version_number, data).unwrap_or_else(|| {
+            panic!(
+                "version number {} does not fit in the envelope",
+                version_number
+            )
+        })
+    }
+
+    /// Builds the envelope of an unknown version, which was read from an
+    /// envelope of the same type. `None` if it cannot hold `version`, e.g. a
+    /// name, which only envelopes of enums with named versions can.
+    fn from_raw_version(version: &RawVersion, data: T) -> Option<Self> {
+        match version {
+            RawVersion::Number(version_number) => Self::try_from_parts(*version_number, data),
+            RawVersion::Name(_) => None,
+        }
+    }
+
+    /// The version number the envelope holds.
+    fn version_number(&self) -> usize;
+
+    /// The version the envelope holds, which may be a name the enum does not
+    /// have.
+    fn version(&self) -> RawVersion {
+        RawVersion::Number(self.version_number())
+    }
+}
+
+impl<T, V: VersionNumber> Envelope<T> for VersionedEnvelope<T, V> {
+    fn try_from_parts(version_number: usize, data: T) -> Option<Self> {
+        Some(VersionedEnvelope {
+            version_number: V::from_usize(version_number)?,
+            data,
+        })
+    }
+
+    fn version_number(&self) -> usize {
+        // Only a `u64` can fail to fit, on 32-bit platforms, where it cannot be
+        // one of the enum's versions either.
+        self.version_number.to_usize().unwrap_or(usize::MAX)
+    }
+}
+
+impl<E: ?Sized, T, V> Envelope<T> for NamedEnvelope<E, T, V> {
+    fn try_from_parts(version_number: usize, data: T) -> Option<Self> {
+        Some(NamedEnvelope::new(version_number, data))
+    }
+
+    fn from_raw_version(version: &RawVersion, data: T) -> Option<Self> {
+        Some(NamedEnvelope::with_version(version.clone(), data))
+    }
+
+    fn version_number(&self) -> usize {
+        self.version_number
+    }
+
+    fn version(&self) -> RawVersion {
+        match &self.unknown_name {
+            Some(name) => RawVersion::Name(name.clone()),
+            None => RawVersion::Number(self.version_number),
+        }
+    }
+}
+
+/// An unsigned integer type which version numbers are written to the envelope
+/// as, chosen with `#[versioned(repr = ..)]`. Defaults to `usize`, which
+/// fixed-width formats such as bincode write in 8 bytes on 64-bit platforms.
+pub trait VersionNumber: Copy + fmt::Display + Serialize + DeserializeOwned + 'static {
+    /// The name of the type, for error messages.
+    const NAME: &'static str;
+
+    fn from_usize(version_number: usize) -> Option<Self>;
+    fn from_u64(version_number: u64) -> Option<Self>;
+    fn to_usize(self) -> Option<usize>;
+}
+
+macro_rules! impl_version_number {
+    ($($ty:ident),*) => {$(
+        impl VersionNumber for $ty {
+            const NAME: &'static str = stringify!($ty);
+
+            fn from_usize(version_number: usize) -> Option<Self> {
+                version_number.try_into().ok()
+            }
+
+            fn from_u64(version_number: u64) -> Option<Self> {
+                version_number.try_into().ok()
+            }
+
+            fn to_usize(self) -> Option<usize> {
+                self.try_into().ok()
+            }
+        }
+    )*};
+}
+
+impl_version_number!(u8, u16, u32, u64, usize);
+
+/// A [`VersionedEnvelope`](crate::VersionedEnvelope) whose field names are
+/// chosen by the versioned enum `E`, with `#[versioned(tag = "..", content =
+/// "..")]`. It is serialized with `E`'s [`VersionedSerialize::TAG`] and
+/// [`VersionedSerialize::CONTENT`], and deserialized with those of its
+/// [`VersionedDeserialize`] impl. The version number is written as a `V`.
+///
+/// A version name which `E` does not have is kept, and written back out, as
+/// it was read; its `version_number` is then `usize::MAX`.
+pub struct NamedEnvelope<E: ?Sized, T, V = usize> {
+    pub version_number: usize,
+    pub data: T,
+    unknown_name: Option<String>,
+    layout: PhantomData<fn() -> (V, E)>,
+}
+
+impl<E: ?Sized, T, V> NamedEnvelope<E, T, V> {
+    pub fn new(version_number: usize, data: T) -> Self {
+        NamedEnvelope::with_version(RawVersion::Number(version_number), data)
+    }
+
+    pub(crate) fn with_version(version: RawVersion, data: T) -> Self {
+        let (version_number, unknown_name) = split_version(version);
+        NamedEnvelope {
+            version_number,
+            data,
+            unknown_name,
+            layout: PhantomData,
+        }
+    }
+}
+
+/// Splits a version into the `version_number` of an envelope and the name the
+/// enum does not have, if it is one.
+pub(crate) fn split_version(version: RawVersion) -> (usize, Option<String>) {
+    match version {
+        RawVersion::Number(version_number) => (version_number, None),
+        RawVersion::Name(name) => (usize::MAX, Some(name)),
+    }
+}
+
+impl<E: ?Sized, T: fmt::Debug, V> fmt::Debug for NamedEnvelope<E, T, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("NamedEnvelope")
+            .field("version", &Envelope::version(self))
+            .field("data", &self.data)
+            .finish()
+    }
+}
+
+impl<E: ?Sized, T: Clone, V> Clone for NamedEnvelope<E, T, V> {
+    fn clone(&self) -> Self {
+        NamedEnvelope::with_version(Envelope::version(self), self.data.clone())
+    }
+}
+
+impl<E: ?Sized, T: PartialEq, V> PartialEq for NamedEnvelope<E, T, V> {
+    fn eq(&self, other: &Self) -> bool {
+        self.version_number == other.version_number
+            && self.unknown_name == other.unknown_name
+            && self.data == other.data
+    }
+}
+
+impl<E, T, V> Serialize for NamedEnvelope<E, T, V>
+where
+    E: VersionedSerialize + ?Sized,
+    T: Serialize,
+    V: VersionNumber,
+{
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut envelope = serializer.serialize_struct("VersionedEnvelope", 2)?;
+        envelope.serialize_field(
+            E::TAG,
+            &VersionRepr::<V>::new(
+                self.version_number,
+                self.unknown_name.as_deref(),
+                E::VERSION_NAMES,
+            ),
+        )?;
+        envelope.serialize_field(E::CONTENT, &self.data)?;
+        envelope.end()
+    }
+}
+
+impl<'de, E, T, V> Deserialize<'de> for NamedEnvelope<E, T, V>
+where
+    E: VersionedDeserialize,
+    T: Deserialize<'de>,
+    V: VersionNumber,
+{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_struct(
+            "VersionedEnvelope",
+            EnvelopeFields::<E>::FIELDS,
+            EnvelopeVisitor(PhantomData),
+        )
+    }
+}
+
+pub(crate) struct EnvelopeFields<E>(PhantomData<E>);
+
+impl<E: VersionedDeserialize> EnvelopeFields<E> {
+    pub(crate) const FIELDS: &'static [&'static str] = &[E::TAG, E::CONTENT];
+}
+
+enum EnvelopeField {
+    Tag,
+    Content,
+    Other,
+}
+
+impl EnvelopeField {
+    fn from_name<E: VersionedDeserialize>(name: &[u8]) -> Self {
+        if name == E::TAG.as_bytes() {
+            EnvelopeField::Tag
+        } else if name == E::CONTENT.as_bytes() {
+            EnvelopeField::Content
+        } else {
+            EnvelopeField::Other
+        }
+    }
+}
+
+struct EnvelopeFieldSeed<E>(PhantomData<E>);
+
+impl<'de, E: VersionedDeserialize> de::DeserializeSeed<'de> for EnvelopeFieldSeed<E> {
+    type Value = EnvelopeField;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        deserializer.deserialize_identifier(self)
+    }
+}
+
+impl<'de, E: VersionedDeserialize> Visitor<'de> for EnvelopeFieldSeed<E> {
+    type Value = EnvelopeField;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "`{}` or `{}`", E::TAG, E::CONTENT)
+    }
+
+    fn visit_str<Err: de::Error>(self, value: &str) -> Result<Self::Value, Err> {
+        Ok(EnvelopeField::from_name::<E>(value.as_bytes()))
+    }
+
+    fn visit_bytes<Err: de::Error>(self, value: &[u8]) -> Result<Self::Value, Err> {
+        Ok(EnvelopeField::from_name::<E>(value))
+    }
+
+    fn visit_u64<Err: de::Error>(self, value: u64) -> Result<Self::Value, Err> {
+        match value {
+            0 => Ok(EnvelopeField::Tag),
+            1 => Ok(EnvelopeField::Content),
+            _ => Ok(EnvelopeField::Other),
+        }
+    }
+}
+
+struct EnvelopeVisitor<E, T, V>(PhantomData<NamedEnvelope<E, T, V>>);
+
+impl<'de, E, T, V> Visitor<'de> for EnvelopeVisitor<E, T, V>
+where
+    E: VersionedDeserialize,
+    T: Deserialize<'de>,
+    V: VersionNumber,
+{
+    type Value = NamedEnvelope<E, T, V>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a versioned envelope")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        let version = seq
+            .next_element_seed(VersionSeed::<V>::new(E::VERSION_NAMES))?
+            .ok_or_else(|| de::Error::invalid_length(0, &self))?;
+        let data = seq
+            .next_element()?
+            .ok_or_else(|| de::Error::invalid_length(1, &self))?;
+        Ok(NamedEnvelope::with_version(version, data))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+        let mut version = None;
+        let mut data = None;
+        while let Some(field) = map.next_key_seed(EnvelopeFieldSeed::<E>(PhantomData))? {
+            match field {
+                EnvelopeField::Tag if version.is_some() => {
+                    return Err(de::Error::duplicate_field(E::TAG));
+                }
+                EnvelopeField::Tag => {
+                    version = Some(map.next_value_seed(VersionSeed::<V>::new(E::VERSION_NAMES))?);
+                }
+                EnvelopeField::Content if data.is_some() => {
+                    return Err(de::Error::duplicate_field(E::CONTENT));
+                }
+                EnvelopeField::Content => data = Some(map.next_value()?),
+                EnvelopeField::Other => {
+                    map.next_value::<IgnoredAny>()?;
+                }
+            }
+        }
+
+        Ok(NamedEnvelope::with_version(
+            version.ok_or_else(|| de::Error::missing_field(E::TAG))?,
+            data.ok_or_else(|| de::Error::missing_field(E::CONTENT))?,
+        ))
+    }
+}
+
+/// Deserializes the versioned enum `E` from its envelope, for the
+/// `Deserialize` impl derived with `#[versioned(serde)]`. The data is read as
+/// the envelope's version, and is buffered if it comes before the version
+/// number.
+#[doc(hidden)]
+pub fn deserialize_enveloped<'de, E, V, D>(deserializer: D) -> Result<E, D::Error>
+where
+    E: VersionedDeserialize + DeserializeVersion<'de>,
+    V: VersionNumber,
+    D: Deserializer<'de>,
+{
+    let human_readable = deserializer.is_human_readable();
+    deserializer.deserialize_struct(
+        "VersionedEnvelope",
+        EnvelopeFields::<E>::FIELDS,
+        VersionDispatchVisitor::<E, V> {
+            human_readable,
+            versioned: PhantomData,
+        },
+    )
+}
+
+struct VersionDispatchVisitor<E, V> {
+    human_readable: bool,
+    versioned: PhantomData<fn() -> (E, V)>,
+}
+
+impl<'de, E, V> Visitor<'de> for VersionDispatchVisitor<E, V>
+where
+    E: VersionedDeserialize + DeserializeVersion<'de>,
+    V: VersionNumber,
+{
+    type Value = E;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a versioned envelope")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<E, A::Error> {
+        let version = seq
+            .next_element_seed(VersionSeed::<V>::new(E::VERSION_NAMES))?
+            .ok_or_else(|| de::Error::invalid_length(0, &self))?;
+        seq.next_element_seed(VersionData::<E>::new(version))?
+            .ok_or_else(|| de::Error::invalid_length(1, &self))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<E, A::Error> {
+        let mut version = None;
+        let mut value = None;
+        let mut buffered = None;
+        while let Some(field) = map.next_key_seed(EnvelopeFieldSeed::<E>(PhantomData))? {
+            match field {
+                EnvelopeField::Tag if version.is_some() => {
+                    return Err(de::Error::duplicate_field(E::TAG));
+                }
+                EnvelopeField::Tag => {
+                    version = Some(map.next_value_seed(VersionSeed::<V>::new(E::VERSION_NAMES))?);
+                }
+                EnvelopeField::Content if value.is_some() || buffered.is_some() => {
+                    return Err(de::Error::duplicate_field(E::CONTENT));
+                }
+                EnvelopeField::Content => match &version {
+                    Some(version) => {
+                        value = Some(map.next_value_seed(VersionData::<E>::new(version.clone()))?);
+                    }
+                    None => buffered = Some(map.next_value::<Content>()?),
+                },
+                EnvelopeField::Other => {
+                    map.next_value::<IgnoredAny>()?;
+                }
+            }
+        }
+
+        match (version, value, buffered) {
+            (_, Some(value), _) => Ok(value),
+            (Some(version), None, Some(buffered)) => E::deserialize_version(
+                version,
+                ContentDeserializer::new(buffered, self.human_readable),
+            ),
+            (None, ..) => Err(de::Error::missing_field(E::TAG)),
+            (Some(_), None, None) => Err(de::Error::missing_field(E::CONTENT)),
+        }
+    }
+}
+
+/// Deserializes the data of version `version` of the versioned enum `E`.
+pub(crate) struct VersionData<E> {
+    version: RawVersion,
+    versioned: PhantomData<fn() -> E>,
+}
+
+impl<E> VersionData<E> {
+    pub(crate) fn new(version: RawVersion) -> Self {
+        VersionData {
+            version,
+            versioned: PhantomData,
+        }
+    }
+}
+
+impl<'de, E: DeserializeVersion<'de>> DeserializeSeed<'de> for VersionData<E> {
+    type Value = E;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<E, D::Error> {
+        E::deserialize_version(self.version, deserializer)
+    }
+}
+
+/// Serializes a version number as a `V`, or the name of a version declared
+/// with `#[versioned(version = "..")]`, or of an unknown version which was read
+/// by name.
+pub(crate) struct VersionRepr<'a, V> {
+    version_number: usize,
+    unknown_name: Option<&'a str>,
+    names: &'static [(usize, &'static str)],
+    repr: PhantomData<V>,
+}
+
+impl<'a, V> VersionRepr<'a, V> {
+    pub(crate) fn new(
+        version_number: usize,
+        unknown_name: Option<&'a str>,
+        names: &'static [(usize, &'static str)],
+    ) -> Self {
+        VersionRepr {
+            version_number,
+            unknown_name,
+            names,
+            repr: PhantomData,
+        }
+    }
+}
+
+impl<V: VersionNumber> Serialize for VersionRepr<'_, V> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if let Some(name) = self.unknown_name {
+            return serializer.serialize_str(name);
+        }
+
+        match self
+            .names
+            .iter()
+            .find(|(version_number, _)| *version_number == self.version_number)
+        {
+            Some((_, name)) => serializer.serialize_str(name),
+            None => V::from_usize(self.version_number)
+                .ok_or_else(|| {
+                    ser::Error::custom(format_args!(
+                        "version number {} does not fit in {}",
+                        self.version_number,
+                        V::NAME
+                    ))
+                })?
+                .serialize(serializer),
+        }
+    }
+}
+
+/// Deserializes a version number written as a `V`, or the name of a version
+/// declared with `#[versioned(version = "..")]` as its number. Names which are
+/// not declared are kept, for the enum's `#[versioned(unknown)]` variant.
+pub(crate) struct VersionSeed<V> {
+    names: &'static [(usize, &'static str)],
+    repr: PhantomData<V>,
+}
+
+impl<V> VersionSeed<V> {
+    pub(crate) fn new(names: &'static [(usize, &'static str)]) -> Self {
+        VersionSeed {
+            names,
+            repr: PhantomData,
+        }
+    }
+}
+
+impl<'de, V: VersionNumber> DeserializeSeed<'de> for VersionSeed<V> {
+    type Value = RawVersion;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<RawVersion, D::Error> {
+        if self.names.is_empty() {
+            let version_number = V::deserialize(deserializer)?;
+            let version_number = version_number.to_usize().ok_or_else(|| {
+                de::Error::custom(format_args!(
+                    "version number {} does not fit in usize",
+                    version_number
+                ))
+            })?;
+            Ok(RawVersion::Number(version_number))
+        } else {
+            deserializer.deserialize_any(self)
+        }
+    }
+}
+
+impl<'de, V: VersionNumber> Visitor<'de> for VersionSeed<V> {
+    type Value = RawVersion;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "a version name or {} version number", V::NAME)
+    }
+
+    fn visit_u64<Err: de::Error>(self, value: u64) -> Result<RawVersion, Err> {
+        match V::from_u64(value).and_then(V::to_usize) {
+            Some(version_number)
+                if !self
+                    .names
+                    .iter()
+                    .any(|(named_version, _)| *named_version == version_number) =>
+            {
+                Ok(RawVersion::Number(version_number))
+            }
+            _ => Err(de::Error::invalid_value(Unexpected::Unsigned(value), &self)),
+        }
+    }
+
+    fn visit_str<Err: de::Error>(self, value: &str) -> Result<RawVersion, Err> {
+        Ok(match self.names.iter().find(|(_, name)| *name == value) {
+            Some((version_number, _)) => RawVersion::Number(*version_number),
+            None => RawVersion::Name(value.into()),
+        })
+    }
+}
+
+/// The version field of data whose envelope could not be decoded, read by
+/// [`probe_version`].
+#[doc(hidden)]
+pub enum ProbedVersion {
+    /// The data has no envelope, e.g. it was written before the structure was
+    /// versioned.
+    Absent,
+    Number(u64),
+    Name(String),
+    /// A version field which is neither a number nor a name.
+    Other,
+}
+
+impl ProbedVersion {
+    /// The error for an envelope holding this version, if it is a version
+    /// number which the versioned enum `E` does not have.
+    pub fn unknown_version<E: VersionedDeserialize>(&self) -> Option<VersionedError> {
+        match *self {
+            ProbedVersion::Number(version_number) => {
+                // Only a `u64` can fail to fit, on 32-bit platforms, where it
+                // cannot be one of the enum's versions either.
+                let version_number = usize::try_from(version_number).unwrap_or(usize::MAX);
+                (!E::VERSIONS.is_empty() && !E::VERSIONS.contains(&version_number))
+                    .then(|| VersionedError::unknown_version::<E>(version_number))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Reads the version field of `data`, whose envelope the versioned enum `E`
+/// could not decode, to tell data without an envelope apart from an envelope
+/// which `E` cannot read (e.g. one holding a version too large for its
+/// `repr`). Data which cannot be read as an envelope at all has none.
+///
+/// In formats which write an envelope as a sequence, such as
+/// [`MsgPackBytes`](crate::MsgPackBytes), data without one is told apart by its
+/// length and first element, so a structure of two fields, the first of which
+/// is a number, reads as an envelope.
+#[doc(hidden)]
+pub fn probe_version<E, F>(data: &F) -> ProbedVersion
+where
+    E: VersionedDeserialize,
+    F: DeserializeFormat,
+{
+    match data.deserialize_format::<VersionProbe<E>>() {
+        Ok(probe) => probe.version,
+        Err(_) => ProbedVersion::Absent,
+    }
+}
+
+struct VersionProbe<E> {
+    version: ProbedVersion,
+    versioned: PhantomData<fn() -> E>,
+}
+
+impl<E> VersionProbe<E> {
+    fn new(version: ProbedVersion) -> Self {
+        VersionProbe {
+            version,
+            versioned: PhantomData,
+        }
+    }
+}
+
+impl<'de, E: VersionedDeserialize> Deserialize<'de> for VersionProbe<E> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_struct(
+            "VersionedEnvelope",
+            EnvelopeFields::<E>::FIELDS,
+            VersionProbeVisitor(PhantomData),
+        )
+    }
+}
+
+struct VersionProbeVisitor<E>(PhantomData<fn() -> E>);
+
+impl<'de, E: VersionedDeserialize> Visitor<'de> for VersionProbeVisitor<E> {
+    type Value = VersionProbe<E>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a versioned envelope")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        let version = match seq.next_element::<ProbedField>()? {
+            Some(ProbedField(ProbedVersion::Number(version_number))) => {
+                ProbedVersion::Number(version_number)
+            }
+            Some(ProbedField(ProbedVersion::Name(name))) if !E::VERSION_NAMES.is_empty() => {
+                ProbedVersion::Name(name)
+            }
+            _ => ProbedVersion::Absent,
+        };
+
+        let mut len = 1;
+        while seq.next_element::<IgnoredAny>()?.is_some() {
+            len += 1;
+        }
+
+        match len {
+            2 => Ok(VersionProbe::new(version)),
+            _ => Ok(VersionProbe::new(ProbedVersion::Absent)),
+        }
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+        let mut version = ProbedVersion::Absent;
+        while let Some(field) = map.next_key_seed(EnvelopeFieldSeed::<E>(PhantomData))? {
+            match field {
+                EnvelopeField::Tag => version = map.next_value::<ProbedField>()?.0,
+                EnvelopeField::Content | EnvelopeField::Other => {
+                    map.next_value::<IgnoredAny>()?;
+                }
+            }
+        }
+
+        Ok(VersionProbe::new(version))
+    }
+}
+
+/// A version field of any type, read by [`probe_version`].
+struct ProbedField(ProbedVersion);
+
+impl<'de> Deserialize<'de> for ProbedField {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(ProbedFieldVisitor)
+    }
+}
+
+struct ProbedFieldVisitor;
+
+impl ProbedFieldVisitor {
+    fn other<Err>(self) -> Result<ProbedField, Err> {
+        Ok(ProbedField(ProbedVersion::Other))
+    }
+}
+
+impl<'de> Visitor<'de> for ProbedFieldVisitor {
+    type Value = ProbedField;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a version field")
+    }
+
+    fn visit_u64<Err: de::Error>(self, value: u64) -> Result<Self::Value, Err> {
+        Ok(ProbedField(ProbedVersion::Number(value)))
+    }
+
+    fn visit_i64<Err: de::Error>(self, value: i64) -> Result<Self::Value, Err> {
+        match u64::try_from(value) {
+            Ok(value) => self.visit_u64(value),
+            Err(_) => self.other(),
+        }
+    }
+
+    fn visit_bool<Err: de::Error>(self, _: bool) -> Result<Self::Value, Err> {
+        self.other()
+    }
+
+    fn visit_f64<Err: de::Error>(self, _: f64) -> Result<Self::Value, Err> {
+        self.other()
+    }
+
+    fn visit_str<Err: de::Error>(self, value: &str) -> Result<Self::Value, Err> {
+        Ok(ProbedField(ProbedVersion::Name(value.to_owned())))
+    }
+
+    fn visit_bytes<Err: de::Error>(self, _: &[u8]) -> Result<Self::Value, Err> {
+        self.other()
+    }
+
+    fn visit_unit<Err: de::Error>(self) -> Result<Self::Value, Err> {
+        self.other()
+    }
+
+    fn visit_none<Err: de::Error>(self) -> Result<Self::Value, Err> {
+        self.other()
+    }
+
+    fn visit_some<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        IgnoredAny::deserialize(deserializer)?;
+        self.other()
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, seq: A) -> Result<Self::Value, A::Error> {
+        IgnoredAny.visit_seq(seq)?;
+        self.other()
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<Self::Value, A::Error> {
+        IgnoredAny.visit_map(map)?;
+        self.other()
+    }
+}
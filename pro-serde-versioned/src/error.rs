@@ -0,0 +1,278 @@
+use alloc::boxed::Box;
+use alloc::string::String;
+use core::any::type_name;
+use core::error::Error;
+use core::fmt;
+
+use crate::{RawVersion, VersionedDeserialize};
+
+/// The error returned when a versioned wrapper cannot be upgraded.
+#[derive(Debug)]
+pub enum UpgradeError {
+    /// One step of a chain upgrade failed, upgrading from version `from` to
+    /// version `to`.
+    Step {
+        from: usize,
+        to: usize,
+        source: Box<dyn Error + Send + Sync>,
+    },
+
+    /// The requested version is not one of the enum's variants.
+    UnknownVersion { version: usize },
+
+    /// No version was declared with the requested name.
+    UnknownVersionName { name: String },
+
+    /// The requested version is older than the current one, so it can only be
+    /// reached by downgrading.
+    OlderVersion { current: usize, target: usize },
+
+    /// The wrapper holds the undecoded data of a version it does not have, in
+    /// its `#[versioned(unknown)]` variant.
+    Undecoded { version: RawVersion },
+}
+
+impl fmt::Display for UpgradeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UpgradeError::Step { from, to, source } => write!(
+                f,
+                "Failed to upgrade from version {} to version {}: {}",
+                from, to, source
+            ),
+            UpgradeError::UnknownVersion { version } => {
+                write!(f, "Cannot upgrade to unknown version {}", version)
+            }
+            UpgradeError::UnknownVersionName { name } => {
+                write!(f, "Cannot upgrade to unknown version {:?}", name)
+            }
+            UpgradeError::OlderVersion { current, target } => write!(
+                f,
+                "Cannot upgrade from version {} to older version {}",
+                current, target
+            ),
+            UpgradeError::Undecoded { version } => {
+                write!(
+                    f,
+                    "Cannot upgrade undecoded data of unknown version {}",
+                    version
+                )
+            }
+        }
+    }
+}
+
+impl Error for UpgradeError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            UpgradeError::Step { source, .. } => Some(source.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+/// The error returned when a versioned wrapper cannot be downgraded to the
+/// requested version.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DowngradeError {
+    /// The requested version is not one of the enum's variants.
+    UnknownVersion { version: usize },
+
+    /// No version was declared with the requested name.
+    UnknownVersionName { name: String },
+
+    /// The requested version is newer than the current one, so it can only be
+    /// reached by upgrading.
+    NewerVersion { current: usize, target: usize },
+
+    /// The wrapper holds the undecoded data of a version it does not have, in
+    /// its `#[versioned(unknown)]` variant.
+    Undecoded { version: RawVersion },
+}
+
+impl fmt::Display for DowngradeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DowngradeError::UnknownVersion { version } => {
+                write!(f, "Cannot downgrade to unknown version {}", version)
+            }
+            DowngradeError::UnknownVersionName { name } => {
+                write!(f, "Cannot downgrade to unknown version {:?}", name)
+            }
+            DowngradeError::NewerVersion { current, target } => write!(
+                f,
+                "Cannot downgrade from version {} to newer version {}",
+                current, target
+            ),
+            DowngradeError::Undecoded { version } => write!(
+                f,
+                "Cannot downgrade undecoded data of unknown version {}",
+                version
+            ),
+        }
+    }
+}
+
+impl Error for DowngradeError {}
+
+/// The error returned when versioned data cannot be read, by
+/// [`VersionedDeserialize::try_versioned_deserialize`]. `versioned_deserialize`
+/// writes the same message into the format's own error.
+#[derive(Debug)]
+pub enum VersionedError {
+    /// The data was written in a version the enum `family` does not have,
+    /// e.g. by a producer which is ahead of this reader.
+    UnknownVersion {
+        family: &'static str,
+        version: RawVersion,
+        supported: &'static [usize],
+    },
+
+    /// The data could not be decoded as the given version of `family`, or, if
+    /// `version` is `None`, its envelope could not be read at all.
+    Decode {
+        family: &'static str,
+        version: Option<usize>,
+        source: Box<dyn Error + Send + Sync>,
+    },
+
+    /// The data was read, but could not be upgraded from version `from` to
+    /// version `to`.
+    Upgrade {
+        family: &'static str,
+        from: usize,
+        to: usize,
+        source: Box<dyn Error + Send + Sync>,
+    },
+}
+
+impl VersionedError {
+    /// The error for data written in `version`, which `E` does not have.
+    pub fn unknown_version<E: VersionedDeserialize>(version: impl Into<RawVersion>) -> Self {
+        VersionedError::UnknownVersion {
+            family: type_name::<E>(),
+            version: version.into(),
+            supported: E::VERSIONS,
+        }
+    }
+
+    pub(crate) fn upgrade<E: VersionedDeserialize>(error: UpgradeError) -> Self {
+        match error {
+            UpgradeError::Step { from, to, source } => VersionedError::Upgrade {
+                family: type_name::<E>(),
+                from,
+                to,
+                source,
+            },
+            UpgradeError::UnknownVersion { version } => {
+                VersionedError::unknown_version::<E>(version)
+            }
+            UpgradeError::Undecoded { version } => VersionedError::unknown_version::<E>(version),
+            // Only upgrading to a version by name can fail to find it, which
+            // upgrading to the latest never does.
+            UpgradeError::UnknownVersionName { .. } => VersionedError::Decode {
+                family: type_name::<E>(),
+                version: None,
+                source: Box::new(error),
+            },
+            UpgradeError::OlderVersion { current, target } => VersionedError::Upgrade {
+                family: type_name::<E>(),
+                from: current,
+                to: target,
+                source: Box::new(error),
+            },
+        }
+    }
+
+    /// The name of the versioned enum the data was read as.
+    pub fn family(&self) -> &'static str {
+        match self {
+            VersionedError::UnknownVersion { family, .. }
+            | VersionedError::Decode { family, .. }
+            | VersionedError::Upgrade { family, .. } => family,
+        }
+    }
+
+    /// Whether the data was written in a version newer than any the enum
+    /// supports, rather than being corrupt.
+    pub fn is_newer_version(&self) -> bool {
+        match self {
+            VersionedError::UnknownVersion {
+                version: RawVersion::Number(version),
+                supported,
+                ..
+            } => supported.iter().all(|supported| supported < version),
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for VersionedError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VersionedError::UnknownVersion {
+                family,
+                version,
+                supported,
+            } => {
+                match version {
+                    RawVersion::Number(version) => {
+                        write!(f, "Unknown version number {} of `{}`", version, family)?
+                    }
+                    RawVersion::Name(_) => {
+                        write!(f, "Unknown version name {} of `{}`", version, family)?
+                    }
+                }
+                for (index, supported) in supported.iter().enumerate() {
+                    let separator = if index == 0 {
+                        "; supported versions are"
+                    } else {
+                        ","
+                    };
+                    write!(f, "{} {}", separator, supported)?;
+                }
+
+                Ok(())
+            }
+            VersionedError::Decode {
+                family,
+                version: Some(version),
+                source,
+            } => write!(
+                f,
+                "Failed to decode version {} of `{}`: {}",
+                version, family, source
+            ),
+            VersionedError::Decode {
+                family,
+                version: None,
+                source,
+            } => write!(
+                f,
+                "Failed to decode the envelope of `{}`: {}",
+                family, source
+            ),
+            VersionedError::Upgrade {
+                family,
+                from,
+                to,
+                source,
+            } => write!(
+                f,
+                "Failed to upgrade `{}` from version {} to version {}: {}",
+                family, from, to, source
+            ),
+        }
+    }
+}
+
+impl Error for VersionedError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            VersionedError::Decode { source, .. } | VersionedError::Upgrade { source, .. } => {
+                Some(source.as_ref())
+            }
+            VersionedError::UnknownVersion { .. } => None,
+        }
+    }
+}
@@ -1,32 +1,93 @@
-use std::borrow::Cow;
+use alloc::borrow::Cow;
 
 use serde::{Deserialize, Serialize};
+#[cfg(feature = "serde_json")]
+use serde_json::value::RawValue;
 
 use crate::{DeserializeFormat, SerializeFormat};
 
 #[cfg(feature = "serde_json")]
 impl SerializeFormat for serde_json::Value {
     type Error = serde_json::Error;
+    type Raw = serde_json::Value;
 
     fn serialize_format<T: Serialize>(data: T) -> Result<Self, Self::Error> {
-        Ok(serde_json::to_value(&data)?)
+        serde_json::to_value(&data)
+    }
+
+    fn from_raw(raw: &serde_json::Value) -> Self {
+        raw.clone()
     }
 }
 
+/// Deserializes through a reference to the value, rather than a copy of it, so
+/// data can borrow strings from it.
 #[cfg(feature = "serde_json")]
 impl DeserializeFormat for serde_json::Value {
     type Error = serde_json::Error;
+    type Raw = serde_json::Value;
 
     fn deserialize_format<'a, T>(&'a self) -> Result<T, Self::Error>
     where
         T: Deserialize<'a>,
     {
-        Ok(T::deserialize(self.clone())?)
+        T::deserialize(self)
+    }
+
+    fn to_raw(&self) -> serde_json::Value {
+        self.clone()
+    }
+}
+
+/// JSON text which has not been parsed yet, borrowed from the input or owned.
+/// Reading an envelope of `JsonRaw` parses only the envelope itself, leaving
+/// the data as text until it is decoded as the version it was written in, so
+/// the version can be peeked at cheaply and the data's strings borrowed
+/// straight from the input. It must be read from JSON text, e.g. with
+/// [`serde_json::from_str`].
+#[cfg(feature = "serde_json")]
+#[derive(Serialize, Debug, Clone)]
+pub struct JsonRaw<'a>(pub Cow<'a, RawValue>);
+
+#[cfg(feature = "serde_json")]
+impl<'de: 'a, 'a> Deserialize<'de> for JsonRaw<'a> {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let json = <&'a RawValue>::deserialize(deserializer)?;
+        Ok(JsonRaw(Cow::Borrowed(json)))
+    }
+}
+
+#[cfg(feature = "serde_json")]
+impl SerializeFormat for JsonRaw<'_> {
+    type Error = serde_json::Error;
+    type Raw = JsonRaw<'static>;
+
+    fn serialize_format<T: Serialize>(data: T) -> Result<Self, Self::Error> {
+        Ok(JsonRaw(Cow::Owned(serde_json::value::to_raw_value(&data)?)))
+    }
+
+    fn from_raw(raw: &JsonRaw<'static>) -> Self {
+        JsonRaw(Cow::Owned(raw.0.as_ref().to_owned()))
+    }
+}
+
+#[cfg(feature = "serde_json")]
+impl<'a> DeserializeFormat for JsonRaw<'a> {
+    type Error = serde_json::Error;
+    type Raw = JsonRaw<'static>;
+
+    fn deserialize_format<'b, T: Deserialize<'b>>(&'b self) -> Result<T, Self::Error> {
+        serde_json::from_str(self.0.get())
+    }
+
+    fn to_raw(&self) -> JsonRaw<'static> {
+        JsonRaw(Cow::Owned(self.0.as_ref().to_owned()))
     }
 }
 
 /// An optionally-owned newtype wrapper for MessagePack bytes as implemented by
-/// the [`rmp_serde`] crate.
+/// the [`rmp_serde`] crate. Structs are written as arrays of their fields, and
+/// read from either arrays or maps of their fields.
 #[cfg(feature = "serde_rmp")]
 #[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
 pub struct MsgPackBytes<'a>(
@@ -38,18 +99,152 @@ pub struct MsgPackBytes<'a>(
 #[cfg(feature = "serde_rmp")]
 impl SerializeFormat for MsgPackBytes<'_> {
     type Error = rmp_serde::encode::Error;
+    type Raw = MsgPackBytes<'static>;
+
     fn serialize_format<T: Serialize>(data: T) -> Result<Self, Self::Error> {
         Ok(MsgPackBytes(Cow::Owned(rmp_serde::to_vec(&data)?)))
     }
+
+    fn from_raw(raw: &MsgPackBytes<'static>) -> Self {
+        MsgPackBytes(Cow::Owned(raw.0.to_vec()))
+    }
 }
 
 #[cfg(feature = "serde_rmp")]
 impl<'a> DeserializeFormat for MsgPackBytes<'a> {
     type Error = rmp_serde::decode::Error;
+    type Raw = MsgPackBytes<'static>;
+
     fn deserialize_format<'b, T: Deserialize<'b>>(&'b self) -> Result<T, Self::Error> {
         match &self.0 {
             Cow::Borrowed(bytes) => Ok(rmp_serde::from_slice(bytes)?),
-            Cow::Owned(bytes) => Ok(rmp_serde::from_slice(&bytes)?),
+            Cow::Owned(bytes) => Ok(rmp_serde::from_slice(bytes)?),
         }
     }
+
+    fn to_raw(&self) -> MsgPackBytes<'static> {
+        MsgPackBytes(Cow::Owned(self.0.to_vec()))
+    }
+}
+
+/// An optionally-owned newtype wrapper for MessagePack bytes as implemented by
+/// the [`rmp_serde`] crate, in which structs are written as maps of their
+/// field names, so that fields can be reordered or inserted without breaking
+/// data already written, at the cost of size. Structs are read from either
+/// maps or arrays of their fields, so data written as [`MsgPackBytes`] can be
+/// read too.
+#[cfg(feature = "serde_rmp")]
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct MsgPackNamedBytes<'a>(
+    #[serde(with = "serde_bytes")]
+    #[serde(borrow)]
+    pub Cow<'a, [u8]>,
+);
+
+#[cfg(feature = "serde_rmp")]
+impl SerializeFormat for MsgPackNamedBytes<'_> {
+    type Error = rmp_serde::encode::Error;
+    type Raw = MsgPackNamedBytes<'static>;
+
+    fn serialize_format<T: Serialize>(data: T) -> Result<Self, Self::Error> {
+        let bytes = rmp_serde::to_vec_named(&data)?;
+        Ok(MsgPackNamedBytes(Cow::Owned(bytes)))
+    }
+
+    fn from_raw(raw: &MsgPackNamedBytes<'static>) -> Self {
+        MsgPackNamedBytes(Cow::Owned(raw.0.to_vec()))
+    }
+}
+
+#[cfg(feature = "serde_rmp")]
+impl<'a> DeserializeFormat for MsgPackNamedBytes<'a> {
+    type Error = rmp_serde::decode::Error;
+    type Raw = MsgPackNamedBytes<'static>;
+
+    fn deserialize_format<'b, T: Deserialize<'b>>(&'b self) -> Result<T, Self::Error> {
+        rmp_serde::from_slice(&self.0)
+    }
+
+    fn to_raw(&self) -> MsgPackNamedBytes<'static> {
+        MsgPackNamedBytes(Cow::Owned(self.0.to_vec()))
+    }
+}
+
+/// An optionally-owned newtype wrapper for CBOR bytes as implemented by the
+/// [`serde_cbor`] crate. Like [`MsgPackBytes`], the envelope holds the data as
+/// a nested byte string, which is borrowed from the input along with the
+/// data's strings. `ciborium` is not used, as it only deserializes owned data.
+#[cfg(feature = "serde_cbor")]
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct CborBytes<'a>(
+    #[serde(with = "serde_bytes")]
+    #[serde(borrow)]
+    pub Cow<'a, [u8]>,
+);
+
+#[cfg(feature = "serde_cbor")]
+impl SerializeFormat for CborBytes<'_> {
+    type Error = serde_cbor::Error;
+    type Raw = CborBytes<'static>;
+
+    fn serialize_format<T: Serialize>(data: T) -> Result<Self, Self::Error> {
+        Ok(CborBytes(Cow::Owned(serde_cbor::to_vec(&data)?)))
+    }
+
+    fn from_raw(raw: &CborBytes<'static>) -> Self {
+        CborBytes(Cow::Owned(raw.0.to_vec()))
+    }
+}
+
+#[cfg(feature = "serde_cbor")]
+impl<'a> DeserializeFormat for CborBytes<'a> {
+    type Error = serde_cbor::Error;
+    type Raw = CborBytes<'static>;
+
+    fn deserialize_format<'b, T: Deserialize<'b>>(&'b self) -> Result<T, Self::Error> {
+        serde_cbor::from_slice(&self.0)
+    }
+
+    fn to_raw(&self) -> CborBytes<'static> {
+        CborBytes(Cow::Owned(self.0.to_vec()))
+    }
+}
+
+/// An optionally-owned newtype wrapper for bytes as implemented by the
+/// [`postcard`] crate, which, unlike the other formats, does not need `std`,
+/// so that embedded targets can share versioned types with their hosts.
+#[cfg(feature = "postcard")]
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct PostcardBytes<'a>(
+    #[serde(with = "serde_bytes")]
+    #[serde(borrow)]
+    pub Cow<'a, [u8]>,
+);
+
+#[cfg(feature = "postcard")]
+impl SerializeFormat for PostcardBytes<'_> {
+    type Error = postcard::Error;
+    type Raw = PostcardBytes<'static>;
+
+    fn serialize_format<T: Serialize>(data: T) -> Result<Self, Self::Error> {
+        Ok(PostcardBytes(Cow::Owned(postcard::to_allocvec(&data)?)))
+    }
+
+    fn from_raw(raw: &PostcardBytes<'static>) -> Self {
+        PostcardBytes(Cow::Owned(raw.0.to_vec()))
+    }
+}
+
+#[cfg(feature = "postcard")]
+impl<'a> DeserializeFormat for PostcardBytes<'a> {
+    type Error = postcard::Error;
+    type Raw = PostcardBytes<'static>;
+
+    fn deserialize_format<'b, T: Deserialize<'b>>(&'b self) -> Result<T, Self::Error> {
+        postcard::from_bytes(&self.0)
+    }
+
+    fn to_raw(&self) -> PostcardBytes<'static> {
+        PostcardBytes(Cow::Owned(self.0.to_vec()))
+    }
 }
@@ -0,0 +1,464 @@
+use alloc::string::String;
+use alloc::vec::{self, Vec};
+use core::fmt;
+use core::marker::PhantomData;
+
+use serde::de::value::MapAccessDeserializer;
+use serde::de::{self, DeserializeSeed, IntoDeserializer, MapAccess, Visitor};
+use serde::ser::{self, Impossible, SerializeMap, SerializeStruct};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::content::{Content, ContentDeserializer};
+use crate::envelope::{split_version, VersionData, VersionRepr, VersionSeed};
+use crate::{
+    DeserializeVersion, Envelope, RawVersion, VersionNumber, VersionedDeserialize,
+    VersionedSerialize,
+};
+
+/// An envelope which merges the version number into the data itself, for enums
+/// marked `#[versioned(internally_tagged = "..")]`, e.g. `{"version": 3,
+/// "name": ..}` rather than `{"version_number": 3, "data": {"name": ..}}`.
+///
+/// The version is stored under `E`'s [`VersionedSerialize::TAG`], so the data
+/// must serialize as a map or a struct, and the format must be self-describing
+/// (e.g. `serde_json::Value`, but not `MsgPackBytes`, whose data is opaque
+/// bytes). The version number is written as a `V`. As in a [`NamedEnvelope`],
+/// a version name which `E` does not have is kept as it was read.
+///
+/// [`NamedEnvelope`]: crate::NamedEnvelope
+pub struct InternallyTaggedEnvelope<E: ?Sized, T, V = usize> {
+    pub version_number: usize,
+    pub data: T,
+    unknown_name: Option<String>,
+    layout: PhantomData<fn() -> (V, E)>,
+}
+
+impl<E: ?Sized, T, V> InternallyTaggedEnvelope<E, T, V> {
+    pub fn new(version_number: usize, data: T) -> Self {
+        InternallyTaggedEnvelope::with_version(RawVersion::Number(version_number), data)
+    }
+
+    fn with_version(version: RawVersion, data: T) -> Self {
+        let (version_number, unknown_name) = split_version(version);
+        InternallyTaggedEnvelope {
+            version_number,
+            data,
+            unknown_name,
+            layout: PhantomData,
+        }
+    }
+}
+
+impl<E: ?Sized, T, V> Envelope<T> for InternallyTaggedEnvelope<E, T, V> {
+    fn try_from_parts(version_number: usize, data: T) -> Option<Self> {
+        Some(InternallyTaggedEnvelope::new(version_number, data))
+    }
+
+    fn from_raw_version(version: &RawVersion, data: T) -> Option<Self> {
+        Some(InternallyTaggedEnvelope::with_version(
+            version.clone(),
+            data,
+        ))
+    }
+
+    fn version_number(&self) -> usize {
+        self.version_number
+    }
+
+    fn version(&self) -> RawVersion {
+        match &self.unknown_name {
+            Some(name) => RawVersion::Name(name.clone()),
+            None => RawVersion::Number(self.version_number),
+        }
+    }
+}
+
+impl<E: ?Sized, T: fmt::Debug, V> fmt::Debug for InternallyTaggedEnvelope<E, T, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("InternallyTaggedEnvelope")
+            .field("version", &Envelope::version(self))
+            .field("data", &self.data)
+            .finish()
+    }
+}
+
+impl<E: ?Sized, T: Clone, V> Clone for InternallyTaggedEnvelope<E, T, V> {
+    fn clone(&self) -> Self {
+        InternallyTaggedEnvelope::with_version(Envelope::version(self), self.data.clone())
+    }
+}
+
+impl<E: ?Sized, T: PartialEq, V> PartialEq for InternallyTaggedEnvelope<E, T, V> {
+    fn eq(&self, other: &Self) -> bool {
+        self.version_number == other.version_number
+            && self.unknown_name == other.unknown_name
+            && self.data == other.data
+    }
+}
+
+impl<E, T, V> Serialize for InternallyTaggedEnvelope<E, T, V>
+where
+    E: VersionedSerialize + ?Sized,
+    T: Serialize,
+    V: VersionNumber,
+{
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.data.serialize(TaggedSerializer {
+            tag: E::TAG,
+            version: VersionRepr::<V>::new(
+                self.version_number,
+                self.unknown_name.as_deref(),
+                E::VERSION_NAMES,
+            ),
+            delegate: serializer,
+        })
+    }
+}
+
+impl<'de, E, T, V> Deserialize<'de> for InternallyTaggedEnvelope<E, T, V>
+where
+    E: VersionedDeserialize,
+    T: Deserialize<'de>,
+    V: VersionNumber,
+{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_map(TaggedVisitor(PhantomData))
+    }
+}
+
+/// Deserializes the internally tagged versioned enum `E`, for the `Deserialize`
+/// impl derived with `#[versioned(serde)]`. The rest of the map is read as the
+/// tagged version, and the entries before the tag are buffered until it is
+/// found.
+#[doc(hidden)]
+pub fn deserialize_internally_tagged<'de, E, V, D>(deserializer: D) -> Result<E, D::Error>
+where
+    E: VersionedDeserialize + DeserializeVersion<'de>,
+    V: VersionNumber,
+    D: Deserializer<'de>,
+{
+    let human_readable = deserializer.is_human_readable();
+    deserializer.deserialize_map(TaggedDispatchVisitor::<E, V> {
+        human_readable,
+        versioned: PhantomData,
+    })
+}
+
+struct TaggedDispatchVisitor<E, V> {
+    human_readable: bool,
+    versioned: PhantomData<fn() -> (E, V)>,
+}
+
+impl<'de, E, V> Visitor<'de> for TaggedDispatchVisitor<E, V>
+where
+    E: VersionedDeserialize + DeserializeVersion<'de>,
+    V: VersionNumber,
+{
+    type Value = E;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "a map with a `{}` version tag", E::TAG)
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<E, A::Error> {
+        let mut buffered = Vec::new();
+        loop {
+            match map.next_key::<Content>()? {
+                Some(key) if key.is_str(E::TAG) => break,
+                Some(key) => buffered.push((key, map.next_value::<Content>()?)),
+                None => return Err(de::Error::missing_field(E::TAG)),
+            }
+        }
+
+        let version = map.next_value_seed(VersionSeed::<V>::new(E::VERSION_NAMES))?;
+        VersionData::<E>::new(version).deserialize(MapAccessDeserializer::new(BufferedMap {
+            buffered: buffered.into_iter(),
+            value: None,
+            map,
+            human_readable: self.human_readable,
+        }))
+    }
+}
+
+/// Passes the entries buffered before the version tag through, then the rest
+/// of the map.
+struct BufferedMap<A> {
+    buffered: vec::IntoIter<(Content, Content)>,
+    value: Option<Content>,
+    map: A,
+    human_readable: bool,
+}
+
+impl<'de, A: MapAccess<'de>> MapAccess<'de> for BufferedMap<A> {
+    type Error = A::Error;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, A::Error> {
+        match self.buffered.next() {
+            Some((key, value)) => {
+                self.value = Some(value);
+                seed.deserialize(ContentDeserializer::new(key, self.human_readable))
+                    .map(Some)
+            }
+            None => self.map.next_key_seed(seed),
+        }
+    }
+
+    fn next_value_seed<S: DeserializeSeed<'de>>(&mut self, seed: S) -> Result<S::Value, A::Error> {
+        match self.value.take() {
+            Some(value) => seed.deserialize(ContentDeserializer::new(value, self.human_readable)),
+            None => self.map.next_value_seed(seed),
+        }
+    }
+}
+
+/// Serializes a map or struct with the version tag as its first entry.
+struct TaggedSerializer<'a, S, V> {
+    tag: &'static str,
+    version: VersionRepr<'a, V>,
+    delegate: S,
+}
+
+impl<S: Serializer, V> TaggedSerializer<'_, S, V> {
+    fn unsupported(&self, kind: &str) -> S::Error {
+        ser::Error::custom(format_args!(
+            "cannot add the `{}` version tag to {}; internally tagged versions must serialize as \
+             a map or a struct",
+            self.tag, kind
+        ))
+    }
+}
+
+impl<S: Serializer, V: VersionNumber> Serializer for TaggedSerializer<'_, S, V> {
+    type Error = S::Error;
+    type Ok = S::Ok;
+    type SerializeMap = S::SerializeMap;
+    type SerializeSeq = Impossible<S::Ok, S::Error>;
+    type SerializeStruct = S::SerializeStruct;
+    type SerializeStructVariant = Impossible<S::Ok, S::Error>;
+    type SerializeTuple = Impossible<S::Ok, S::Error>;
+    type SerializeTupleStruct = Impossible<S::Ok, S::Error>;
+    type SerializeTupleVariant = Impossible<S::Ok, S::Error>;
+
+    fn serialize_bool(self, _: bool) -> Result<S::Ok, S::Error> {
+        Err(self.unsupported("a boolean"))
+    }
+
+    fn serialize_i8(self, _: i8) -> Result<S::Ok, S::Error> {
+        Err(self.unsupported("an integer"))
+    }
+
+    fn serialize_i16(self, _: i16) -> Result<S::Ok, S::Error> {
+        Err(self.unsupported("an integer"))
+    }
+
+    fn serialize_i32(self, _: i32) -> Result<S::Ok, S::Error> {
+        Err(self.unsupported("an integer"))
+    }
+
+    fn serialize_i64(self, _: i64) -> Result<S::Ok, S::Error> {
+        Err(self.unsupported("an integer"))
+    }
+
+    fn serialize_u8(self, _: u8) -> Result<S::Ok, S::Error> {
+        Err(self.unsupported("an integer"))
+    }
+
+    fn serialize_u16(self, _: u16) -> Result<S::Ok, S::Error> {
+        Err(self.unsupported("an integer"))
+    }
+
+    fn serialize_u32(self, _: u32) -> Result<S::Ok, S::Error> {
+        Err(self.unsupported("an integer"))
+    }
+
+    fn serialize_u64(self, _: u64) -> Result<S::Ok, S::Error> {
+        Err(self.unsupported("an integer"))
+    }
+
+    fn serialize_f32(self, _: f32) -> Result<S::Ok, S::Error> {
+        Err(self.unsupported("a float"))
+    }
+
+    fn serialize_f64(self, _: f64) -> Result<S::Ok, S::Error> {
+        Err(self.unsupported("a float"))
+    }
+
+    fn serialize_char(self, _: char) -> Result<S::Ok, S::Error> {
+        Err(self.unsupported("a char"))
+    }
+
+    fn serialize_str(self, _: &str) -> Result<S::Ok, S::Error> {
+        Err(self.unsupported("a string"))
+    }
+
+    fn serialize_bytes(self, _: &[u8]) -> Result<S::Ok, S::Error> {
+        Err(self.unsupported("bytes"))
+    }
+
+    fn serialize_none(self) -> Result<S::Ok, S::Error> {
+        Err(self.unsupported("an option"))
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, _: &T) -> Result<S::Ok, S::Error> {
+        Err(self.unsupported("an option"))
+    }
+
+    fn serialize_unit(self) -> Result<S::Ok, S::Error> {
+        Err(self.unsupported("a unit"))
+    }
+
+    fn serialize_unit_struct(self, _: &'static str) -> Result<S::Ok, S::Error> {
+        Err(self.unsupported("a unit struct"))
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _: &'static str,
+        _: u32,
+        _: &'static str,
+    ) -> Result<S::Ok, S::Error> {
+        Err(self.unsupported("an enum"))
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _: &'static str,
+        value: &T,
+    ) -> Result<S::Ok, S::Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _: &'static str,
+        _: u32,
+        _: &'static str,
+        _: &T,
+    ) -> Result<S::Ok, S::Error> {
+        Err(self.unsupported("an enum"))
+    }
+
+    fn serialize_seq(self, _: Option<usize>) -> Result<Self::SerializeSeq, S::Error> {
+        Err(self.unsupported("a sequence"))
+    }
+
+    fn serialize_tuple(self, _: usize) -> Result<Self::SerializeTuple, S::Error> {
+        Err(self.unsupported("a tuple"))
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _: &'static str,
+        _: usize,
+    ) -> Result<Self::SerializeTupleStruct, S::Error> {
+        Err(self.unsupported("a tuple struct"))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _: &'static str,
+        _: u32,
+        _: &'static str,
+        _: usize,
+    ) -> Result<Self::SerializeTupleVariant, S::Error> {
+        Err(self.unsupported("an enum"))
+    }
+
+    fn serialize_map(self, len: Option<usize>) -> Result<Self::SerializeMap, S::Error> {
+        let mut map = self.delegate.serialize_map(len.map(|len| len + 1))?;
+        map.serialize_entry(self.tag, &self.version)?;
+        Ok(map)
+    }
+
+    fn serialize_struct(
+        self,
+        name: &'static str,
+        len: usize,
+    ) -> Result<Self::SerializeStruct, S::Error> {
+        let mut state = self.delegate.serialize_struct(name, len + 1)?;
+        state.serialize_field(self.tag, &self.version)?;
+        Ok(state)
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _: &'static str,
+        _: u32,
+        _: &'static str,
+        _: usize,
+    ) -> Result<Self::SerializeStructVariant, S::Error> {
+        Err(self.unsupported("an enum"))
+    }
+}
+
+struct TaggedVisitor<E, T, V>(PhantomData<InternallyTaggedEnvelope<E, T, V>>);
+
+impl<'de, E, T, V> Visitor<'de> for TaggedVisitor<E, T, V>
+where
+    E: VersionedDeserialize,
+    T: Deserialize<'de>,
+    V: VersionNumber,
+{
+    type Value = InternallyTaggedEnvelope<E, T, V>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "a map with a `{}` version tag", E::TAG)
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<Self::Value, A::Error> {
+        let mut version = None;
+        let data = T::deserialize(MapAccessDeserializer::new(StripTag::<A, V> {
+            map,
+            tag: E::TAG,
+            names: E::VERSION_NAMES,
+            version: &mut version,
+            repr: PhantomData,
+        }))?;
+
+        let version = version.ok_or_else(|| de::Error::missing_field(E::TAG))?;
+        Ok(InternallyTaggedEnvelope::with_version(version, data))
+    }
+}
+
+/// Passes every entry of a map through, except the version tag, which it
+/// stores in `version`.
+struct StripTag<'v, A, V> {
+    map: A,
+    tag: &'static str,
+    names: &'static [(usize, &'static str)],
+    version: &'v mut Option<RawVersion>,
+    repr: PhantomData<V>,
+}
+
+impl<'de, A: MapAccess<'de>, V: VersionNumber> MapAccess<'de> for StripTag<'_, A, V> {
+    type Error = A::Error;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, A::Error> {
+        while let Some(key) = self.map.next_key::<String>()? {
+            if key != self.tag {
+                return seed.deserialize(key.into_deserializer()).map(Some);
+            }
+
+            if self.version.is_some() {
+                return Err(de::Error::duplicate_field(self.tag));
+            }
+
+            *self.version = Some(
+                self.map
+                    .next_value_seed(VersionSeed::<V>::new(self.names))?,
+            );
+        }
+
+        Ok(None)
+    }
+
+    fn next_value_seed<S: DeserializeSeed<'de>>(&mut self, seed: S) -> Result<S::Value, A::Error> {
+        self.map.next_value_seed(seed)
+    }
+}
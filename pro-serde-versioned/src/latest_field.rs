@@ -0,0 +1,41 @@
+use core::marker::PhantomData;
+
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::content::BufferedFormat;
+use crate::{
+    Envelope, KeepsUnknown, VersionOf, VersionedDeserialize, VersionedSerialize, VersionedUpgrade,
+};
+
+/// A `#[serde(with = "..")]` adapter for a field holding the latest version of
+/// the versioned enum `E` rather than the enum itself, e.g.
+/// `#[serde(with = "pro_serde_versioned::latest::<MyStructVersion>")] config:
+/// MyStructV3`. Any version of `E` is read and upgraded to the latest, and the
+/// latest version is written in `E`'s envelope.
+#[allow(non_camel_case_types)]
+pub struct latest<E>(PhantomData<E>);
+
+impl<E: VersionedUpgrade> latest<E> {
+    pub fn serialize<S>(value: &E::Latest, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        E: VersionedSerialize,
+        E::Latest: VersionOf<E> + Serialize,
+        for<'a> E::VersionedEnvelope<&'a E::Latest>: Envelope<&'a E::Latest>,
+        S: Serializer,
+    {
+        <E::VersionedEnvelope<&E::Latest>>::from_parts(E::Latest::VERSION, value)
+            .serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<E::Latest, D::Error>
+    where
+        E: VersionedDeserialize + KeepsUnknown<()>,
+        D: Deserializer<'de>,
+    {
+        let data = BufferedFormat::deserialize(deserializer)?;
+        E::versioned_deserialize(&data)
+            .map(E::upgrade_to_latest)
+            .map_err(D::Error::custom)
+    }
+}
@@ -10,14 +10,58 @@
 // └───────────────────────────────────────────────────────────────────────────┘
 
 #![doc = include_str!("../README.md")]
+#![cfg_attr(not(feature = "std"), no_std)]
 
+extern crate alloc;
+
+use alloc::borrow::ToOwned;
+use alloc::boxed::Box;
+use core::convert::Infallible;
+
+#[cfg(feature = "bincode")]
+mod bincode_bytes;
+mod content;
+mod envelope;
+mod error;
+#[cfg(any(
+    feature = "serde_json",
+    feature = "serde_rmp",
+    feature = "serde_cbor",
+    feature = "postcard"
+))]
 mod formats;
+mod internally_tagged;
+mod latest_field;
+#[cfg(feature = "serde_rmp")]
+mod msgpack_native;
+mod raw_envelope;
+#[cfg(feature = "serde_json")]
+mod sorted_json;
 
 #[cfg(feature = "derive")]
-pub use pro_serde_versioned_derive::{VersionedDeserialize, VersionedSerialize, VersionedUpgrade};
+pub use pro_serde_versioned_derive::{
+    Versioned, VersionedDeserialize, VersionedDowngrade, VersionedSerialize, VersionedUpgrade,
+};
+#[doc(hidden)]
+pub use serde;
 use serde::{Deserialize, Serialize};
 
+#[cfg(feature = "bincode")]
+pub use crate::bincode_bytes::*;
+pub use crate::envelope::*;
+pub use crate::error::*;
+#[cfg(any(
+    feature = "serde_json",
+    feature = "serde_rmp",
+    feature = "serde_cbor",
+    feature = "postcard"
+))]
 pub use crate::formats::*;
+pub use crate::internally_tagged::*;
+pub use crate::latest_field::*;
+#[cfg(feature = "serde_rmp")]
+pub use crate::msgpack_native::*;
+pub use crate::raw_envelope::*;
 
 /// Derivable trait used to chain upgrade a versioned wrapper to the latest
 /// version of a structure (e.g. v1 -> v2 -> ... -> latest)
@@ -26,52 +70,381 @@ pub trait VersionedUpgrade {
     fn upgrade_to_latest(self) -> Self::Latest;
 }
 
+/// Derivable trait used to chain upgrade a versioned wrapper to the latest
+/// version of a structure, where any step may fail. Derived alongside
+/// [`VersionedUpgrade`], and also derived on its own for enums marked
+/// `#[versioned(fallible)]`.
+pub trait TryVersionedUpgrade: Sized {
+    type Latest;
+
+    /// The names of versions declared with `#[versioned(version = "..")]`, by
+    /// version number, which can be upgraded to by name.
+    const VERSION_NAMES: &'static [(usize, &'static str)] = &[];
+
+    fn try_upgrade_to_latest(self) -> Result<Self::Latest, UpgradeError>;
+
+    /// Upgrades to the given version, which need not be the latest (e.g. v1 ->
+    /// v2 -> v3 for a wrapper which also has a v4).
+    fn upgrade_to_version(self, version: usize) -> Result<Self, UpgradeError>;
+
+    /// Upgrades to the version declared with `#[versioned(version = "..")]`
+    /// under the given name, without relying on the number it was given.
+    fn upgrade_to_named_version(self, name: &str) -> Result<Self, UpgradeError> {
+        match Self::VERSION_NAMES
+            .iter()
+            .find(|(_, version_name)| *version_name == name)
+        {
+            Some((version, _)) => self.upgrade_to_version(*version),
+            None => Err(UpgradeError::UnknownVersionName {
+                name: name.to_owned(),
+            }),
+        }
+    }
+
+    /// Upgrades to the version of the structure `T` (e.g.
+    /// `upgrade_to::<MyStructV3>()`).
+    fn upgrade_to<T>(self) -> Result<T, UpgradeError>
+    where
+        T: VersionOf<Self>,
+    {
+        T::from_versioned(self.upgrade_to_version(T::VERSION)?).map_err(|_| {
+            UpgradeError::UnknownVersion {
+                version: T::VERSION,
+            }
+        })
+    }
+}
+
 /// Defines the next version of a given upgradable type (e.g. mystructv1 ->
 /// mystructv1)
 pub trait Upgrade<To> {
     fn upgrade(self) -> To;
 }
 
+/// Defines the next version of a given upgradable type, for upgrades which
+/// can fail (e.g. a field that must be parsed). Implemented for every
+/// [`Upgrade`].
+pub trait TryUpgrade<To> {
+    type Error: Into<Box<dyn core::error::Error + Send + Sync>>;
+    fn try_upgrade(self) -> Result<To, Self::Error>;
+}
+
+impl<From, To> TryUpgrade<To> for From
+where
+    From: Upgrade<To>,
+{
+    type Error = Infallible;
+
+    fn try_upgrade(self) -> Result<To, Self::Error> {
+        Ok(self.upgrade())
+    }
+}
+
+/// Derivable trait used to chain downgrade a versioned wrapper to an older
+/// version of a structure (e.g. latest -> ... -> v2), so that data can be
+/// written in a version older readers understand.
+pub trait VersionedDowngrade: Sized {
+    /// The names of versions declared with `#[versioned(version = "..")]`, by
+    /// version number, which can be downgraded to by name.
+    const VERSION_NAMES: &'static [(usize, &'static str)] = &[];
+
+    fn downgrade_to_version(self, version: usize) -> Result<Self, DowngradeError>;
+
+    /// Downgrades to the version declared with `#[versioned(version = "..")]`
+    /// under the given name, without relying on the number it was given.
+    fn downgrade_to_named_version(self, name: &str) -> Result<Self, DowngradeError> {
+        match Self::VERSION_NAMES
+            .iter()
+            .find(|(_, version_name)| *version_name == name)
+        {
+            Some((version, _)) => self.downgrade_to_version(*version),
+            None => Err(DowngradeError::UnknownVersionName {
+                name: name.to_owned(),
+            }),
+        }
+    }
+
+    fn serialize_as_version<F>(&self, version: usize) -> Result<F, F::Error>
+    where
+        Self: VersionedSerialize + WritesUnknown<F::Raw> + Clone,
+        F: SerializeFormat,
+    {
+        self.clone()
+            .downgrade_to_version(version)
+            .map_err(serde::ser::Error::custom)?
+            .versioned_serialize()
+    }
+
+    fn serialize_as_named_version<F>(&self, name: &str) -> Result<F, F::Error>
+    where
+        Self: VersionedSerialize + WritesUnknown<F::Raw> + Clone,
+        F: SerializeFormat,
+    {
+        self.clone()
+            .downgrade_to_named_version(name)
+            .map_err(serde::ser::Error::custom)?
+            .versioned_serialize()
+    }
+}
+
+/// Defines the previous version of a given downgradable type (e.g. mystructv2
+/// -> mystructv1)
+pub trait Downgrade<To> {
+    fn downgrade(self) -> To;
+}
+
+/// Implemented by each version of a structure for the versioned wrapper enum
+/// (its "family") it belongs to. Keyed by the family, so the same structure
+/// can be a version of several families. Derived by [`Versioned`].
+pub trait VersionOf<Family>: Sized {
+    const VERSION: usize;
+    fn from_versioned(versioned: Family) -> Result<Self, Family>;
+
+    /// Serializes this structure in the family's envelope, as if it had been
+    /// wrapped in the family and serialized with
+    /// [`VersionedSerialize::versioned_serialize`].
+    fn versioned_serialize_as<F>(&self) -> Result<F, F::Error>
+    where
+        Self: Serialize,
+        Family: VersionedSerialize,
+        Family::VersionedEnvelope<F>: Envelope<F>,
+        F: SerializeFormat,
+    {
+        F::serialize_format(<Family::VersionedEnvelope<F>>::from_parts(
+            Self::VERSION,
+            F::serialize_format(self)?,
+        ))
+    }
+}
+
 /// Allows for serializing to any supported format.
 pub trait VersionedSerialize {
+    /// The envelope field holding the version number, set with
+    /// `#[versioned(tag = "..")]` or `#[versioned(internally_tagged = "..")]`.
+    const TAG: &'static str = "version_number";
+
+    /// The envelope field holding the data, set with
+    /// `#[versioned(content = "..")]`.
+    const CONTENT: &'static str = "data";
+
+    /// The names of versions declared with `#[versioned(version = "..")]`, by
+    /// version number, which are written to the envelope in place of their
+    /// numbers.
+    const VERSION_NAMES: &'static [(usize, &'static str)] = &[];
+
     type VersionedEnvelope<F: Serialize>: Serialize;
 
     fn to_envelope<F>(&self) -> Result<Self::VersionedEnvelope<F>, F::Error>
     where
+        Self: WritesUnknown<F::Raw>,
         F: SerializeFormat;
 
     fn versioned_serialize<F>(&self) -> Result<F, F::Error>
     where
+        Self: WritesUnknown<F::Raw>,
         F: SerializeFormat,
     {
-        Ok(F::serialize_format(self.to_envelope::<F>()?)?)
+        F::serialize_format(self.to_envelope::<F>()?)
+    }
+
+    /// Serializes the envelope straight to `serializer`, without serializing
+    /// the data into a format first. Writes the same values as serializing the
+    /// [`serde_json::Value`] returned by
+    /// [`versioned_serialize`](VersionedSerialize::versioned_serialize), but
+    /// with each struct's fields in declaration order, rather than sorted as
+    /// `serde_json::Value` sorts them.
+    fn versioned_serialize_into<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer;
+
+    /// Writes the envelope as JSON to `writer`, without building a
+    /// [`serde_json::Value`]. The output is byte for byte that of
+    /// `serde_json::to_writer(writer,
+    /// &self.versioned_serialize::<serde_json::Value>()?)`: each object's
+    /// keys are sorted as `serde_json::Value` sorts them, which
+    /// holds an object until it ends; arrays and scalars are written as they
+    /// are serialized.
+    #[cfg(feature = "serde_json")]
+    fn versioned_serialize_json<W>(&self, writer: W) -> Result<(), serde_json::Error>
+    where
+        W: std::io::Write,
+    {
+        self.versioned_serialize_into(&mut sorted_json::SortedJson::new(writer))
     }
 }
 
 /// Allows for serializing from any supported format.
 pub trait VersionedDeserialize: Sized + Clone {
+    /// The envelope field holding the version number, set with
+    /// `#[versioned(tag = "..")]` or `#[versioned(internally_tagged = "..")]`.
+    const TAG: &'static str = "version_number";
+
+    /// The envelope field holding the data, set with
+    /// `#[versioned(content = "..")]`.
+    const CONTENT: &'static str = "data";
+
+    /// The names of versions declared with `#[versioned(version = "..")]`, by
+    /// version number, which are read from the envelope as their numbers.
+    const VERSION_NAMES: &'static [(usize, &'static str)] = &[];
+
+    /// The version numbers of the enum's variants, in order, reported by
+    /// [`VersionedError::UnknownVersion`]. If empty, every version is passed to
+    /// [`from_envelope`](VersionedDeserialize::from_envelope).
+    const VERSIONS: &'static [usize] = &[];
+
+    /// Whether the enum has a `#[versioned(unknown)]` variant, which holds the
+    /// data of every version not in
+    /// [`VERSIONS`](VersionedDeserialize::VERSIONS) rather than failing to
+    /// read it.
+    const KEEPS_UNKNOWN_VERSIONS: bool = false;
+
     type VersionedEnvelope<'a, F: Deserialize<'a>>: Deserialize<'a>;
 
     fn from_envelope<'a, F>(data: &Self::VersionedEnvelope<'a, F>) -> Result<Self, F::Error>
     where
+        Self: KeepsUnknown<F::Raw>,
         F: DeserializeFormat + Deserialize<'a>;
 
     fn versioned_deserialize<'a, F>(data: &'a F) -> Result<Self, F::Error>
     where
+        Self: KeepsUnknown<F::Raw>,
         F: DeserializeFormat + Deserialize<'a>,
     {
         let envelope: Self::VersionedEnvelope<'a, F> = F::deserialize_format(data)?;
         Self::from_envelope(&envelope)
     }
+
+    /// Deserializes `data`, or if it has no envelope (e.g. it was written
+    /// before the structure was versioned), decodes the whole of it as the
+    /// given version. Data with an envelope which cannot be decoded, such as
+    /// one holding an unknown version, is an error
+    /// rather than unversioned, as is a `version` the enum does not have. Enums
+    /// marked `#[versioned(unversioned = N)]` do this in
+    /// [`versioned_deserialize`](VersionedDeserialize::versioned_deserialize).
+    fn versioned_deserialize_or_unversioned<'a, F>(
+        data: &'a F,
+        version: usize,
+    ) -> Result<Self, F::Error>
+    where
+        Self: KeepsUnknown<F::Raw>,
+        F: DeserializeFormat + Deserialize<'a> + Clone,
+        Self::VersionedEnvelope<'a, F>: Envelope<F>,
+    {
+        match F::deserialize_format::<Self::VersionedEnvelope<'a, F>>(data) {
+            Ok(envelope) => Self::from_envelope(&envelope),
+            Err(error) => match probe_version::<Self, F>(data) {
+                ProbedVersion::Absent => match Envelope::try_from_parts(version, data.clone()) {
+                    Some(envelope)
+                        if Self::KEEPS_UNKNOWN_VERSIONS
+                            || Self::VERSIONS.is_empty()
+                            || Self::VERSIONS.contains(&version) =>
+                    {
+                        Self::from_envelope(&envelope)
+                    }
+                    _ => {
+                        let error = VersionedError::unknown_version::<Self>(version);
+                        Err(serde::de::Error::custom(error))
+                    }
+                },
+                probed => Err(probed
+                    .unknown_version::<Self>()
+                    .map_or(error, serde::de::Error::custom)),
+            },
+        }
+    }
+
+    /// Deserializes `data` like
+    /// [`versioned_deserialize`](VersionedDeserialize::versioned_deserialize),
+    /// but with a [`VersionedError`] which tells an unknown version apart from
+    /// data which cannot be decoded.
+    fn try_versioned_deserialize<'a, F>(data: &'a F) -> Result<Self, VersionedError>
+    where
+        Self: KeepsUnknown<F::Raw>,
+        F: DeserializeFormat + Deserialize<'a>,
+        F::Error: core::error::Error + Send + Sync + 'static,
+        Self::VersionedEnvelope<'a, F>: Envelope<F>,
+    {
+        let envelope = match F::deserialize_format::<Self::VersionedEnvelope<'a, F>>(data) {
+            Ok(envelope) => envelope,
+            // Enums marked `#[versioned(unversioned = N)]` can still decode data
+            // without an envelope.
+            Err(error) => {
+                if let Some(unknown) = probe_version::<Self, F>(data).unknown_version::<Self>() {
+                    return Err(unknown);
+                }
+
+                return Self::versioned_deserialize(data).map_err(|_| VersionedError::Decode {
+                    family: core::any::type_name::<Self>(),
+                    version: None,
+                    source: Box::new(error),
+                });
+            }
+        };
+
+        // Unknown version names are only read by enums with named versions,
+        // none of which has them.
+        let version = envelope.version();
+        let version_number = match version {
+            RawVersion::Number(version_number) => Some(version_number),
+            RawVersion::Name(_) => None,
+        };
+        let known = version_number.is_some_and(|version_number| {
+            Self::VERSIONS.is_empty() || Self::VERSIONS.contains(&version_number)
+        });
+        if !Self::KEEPS_UNKNOWN_VERSIONS && !known {
+            return Err(VersionedError::unknown_version::<Self>(version));
+        }
+
+        Self::from_envelope(&envelope).map_err(|error| VersionedError::Decode {
+            family: core::any::type_name::<Self>(),
+            version: version_number,
+            source: Box::new(error),
+        })
+    }
+
+    /// Deserializes `data` with
+    /// [`try_versioned_deserialize`](VersionedDeserialize::try_versioned_deserialize)
+    /// and upgrades it to the latest version.
+    fn try_versioned_deserialize_latest<'a, F>(
+        data: &'a F,
+    ) -> Result<<Self as TryVersionedUpgrade>::Latest, VersionedError>
+    where
+        Self: TryVersionedUpgrade + KeepsUnknown<F::Raw>,
+        F: DeserializeFormat + Deserialize<'a>,
+        F::Error: core::error::Error + Send + Sync + 'static,
+        Self::VersionedEnvelope<'a, F>: Envelope<F>,
+    {
+        Self::try_versioned_deserialize(data)?
+            .try_upgrade_to_latest()
+            .map_err(VersionedError::upgrade::<Self>)
+    }
+}
+
+/// Deserializes the data of one version of a versioned enum from any serde
+/// deserializer, once its version has been read from the envelope. Derived by
+/// [`VersionedDeserialize`] for enums marked `#[versioned(serde)]`, whose
+/// `Deserialize` impls are built on it.
+pub trait DeserializeVersion<'de>: Sized {
+    fn deserialize_version<D>(version: RawVersion, deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>;
 }
 
 /// Serialize to the underlying format of a given serialization standard. (e.g.
 /// [serde_json::Value] for JSON, [std::borrow::Cow] of bytes for MsgPack, etc.)
 pub trait SerializeFormat: Sized + Serialize {
     type Error: serde::ser::Error;
+
+    /// The owned data which the [`RawEnvelope`] of a `#[versioned(unknown)]`
+    /// variant keeps for this format, usually the format itself.
+    type Raw;
+
     fn serialize_format<T>(data: T) -> Result<Self, Self::Error>
     where
         T: Serialize;
+
+    /// Converts the data of a [`RawEnvelope`] back to this format.
+    fn from_raw(raw: &Self::Raw) -> Self;
 }
 
 /// Deserialize from the underlying format of a given serialization standard.
@@ -79,17 +452,27 @@ pub trait SerializeFormat: Sized + Serialize {
 /// etc.)
 pub trait DeserializeFormat: Sized {
     type Error: serde::de::Error;
+
+    /// The owned data which the [`RawEnvelope`] of a `#[versioned(unknown)]`
+    /// variant keeps for this format, usually the format itself.
+    type Raw;
+
     fn deserialize_format<'a, T>(&'a self) -> Result<T, Self::Error>
     where
         T: Deserialize<'a>;
+
+    /// An owned copy of this data, for the [`RawEnvelope`] of a
+    /// `#[versioned(unknown)]` variant.
+    fn to_raw(&self) -> Self::Raw;
 }
 
 /// Versioned wrapper for the underlying data format.
 /// Allows for partial deserialization of the data, and for
 /// the version number to be used to determine which
-/// deserialization method to use.
+/// deserialization method to use. The version number is written as a `V`,
+/// chosen with `#[versioned(repr = ..)]`.
 #[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
-pub struct VersionedEnvelope<T> {
-    pub version_number: usize,
+pub struct VersionedEnvelope<T, V = usize> {
+    pub version_number: V,
     pub data: T,
 }
@@ -0,0 +1,522 @@
+use std::borrow::Cow;
+use std::fmt;
+
+use serde::de::{self, DeserializeSeed, MapAccess, SeqAccess, Visitor};
+use serde::ser::{self, SerializeStruct};
+use serde::{Deserialize, Serialize};
+
+use crate::{DeserializeFormat, SerializeFormat};
+
+/// The newtype name `MsgPackNativeBytes` serializes through, so the
+/// serializer and deserializer of the native layout can recognize it and
+/// write or read its bytes as they are.
+const NATIVE_TOKEN: &str = "$pro_serde_versioned::MsgPackNativeBytes";
+
+/// MessagePack bytes as implemented by the [`rmp_serde`] crate, which an
+/// envelope holds as an ordinary nested value, e.g. `92 01 91 a6 ..` for
+/// version 1, rather than as a byte string of MessagePack as
+/// [`MsgPackBytes`](crate::MsgPackBytes) does. Generic MessagePack tools can
+/// read the whole envelope, the data is parsed only once, when it is decoded
+/// as its version, and peeking at the version skips the data without parsing
+/// it.
+///
+/// Envelopes in the nested layout of `MsgPackBytes` are read too, so data
+/// written by it stays readable, and is written back out in the native
+/// layout. Because of this, data which is itself a MessagePack byte string
+/// (e.g. a `serde_bytes::ByteBuf`, rather than a struct holding one) cannot be
+/// written in the native layout, and serializing an envelope of it fails;
+/// write it with `MsgPackBytes` instead.
+#[derive(Debug, PartialEq, Clone)]
+pub struct MsgPackNativeBytes<'a>(pub Cow<'a, [u8]>);
+
+impl Serialize for MsgPackNativeBytes<'_> {
+    fn serialize<S: ser::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_newtype_struct(NATIVE_TOKEN, serde_bytes::Bytes::new(&self.0))
+    }
+}
+
+impl<'de: 'a, 'a> Deserialize<'de> for MsgPackNativeBytes<'a> {
+    fn deserialize<D: de::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_newtype_struct(NATIVE_TOKEN, NativeBytesVisitor)
+    }
+}
+
+struct NativeBytesVisitor;
+
+impl<'de> Visitor<'de> for NativeBytesVisitor {
+    type Value = MsgPackNativeBytes<'de>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("MessagePack bytes")
+    }
+
+    fn visit_borrowed_bytes<E: de::Error>(self, v: &'de [u8]) -> Result<Self::Value, E> {
+        Ok(MsgPackNativeBytes(Cow::Borrowed(v)))
+    }
+
+    fn visit_bytes<E: de::Error>(self, v: &[u8]) -> Result<Self::Value, E> {
+        Ok(MsgPackNativeBytes(Cow::Owned(v.to_vec())))
+    }
+
+    fn visit_byte_buf<E: de::Error>(self, v: Vec<u8>) -> Result<Self::Value, E> {
+        Ok(MsgPackNativeBytes(Cow::Owned(v)))
+    }
+
+    /// Any other deserializer only has the nested layout to offer.
+    fn visit_newtype_struct<D: de::Deserializer<'de>>(
+        self,
+        deserializer: D,
+    ) -> Result<Self::Value, D::Error> {
+        let bytes: Cow<'de, [u8]> = serde_bytes::deserialize(deserializer)?;
+        Ok(MsgPackNativeBytes(bytes))
+    }
+}
+
+impl SerializeFormat for MsgPackNativeBytes<'_> {
+    type Error = rmp_serde::encode::Error;
+    type Raw = MsgPackNativeBytes<'static>;
+
+    fn serialize_format<T: Serialize>(data: T) -> Result<Self, Self::Error> {
+        let mut serializer = NativeSerializer {
+            inner: rmp_serde::Serializer::new(Vec::new()),
+            raw: false,
+        };
+        data.serialize(&mut serializer)?;
+        Ok(MsgPackNativeBytes(Cow::Owned(
+            serializer.inner.into_inner(),
+        )))
+    }
+
+    fn from_raw(raw: &MsgPackNativeBytes<'static>) -> Self {
+        MsgPackNativeBytes(Cow::Owned(raw.0.to_vec()))
+    }
+}
+
+impl<'a> DeserializeFormat for MsgPackNativeBytes<'a> {
+    type Error = rmp_serde::decode::Error;
+    type Raw = MsgPackNativeBytes<'static>;
+
+    fn deserialize_format<'b, T: Deserialize<'b>>(&'b self) -> Result<T, Self::Error> {
+        T::deserialize(NativeDeserializer {
+            input: &self.0,
+            envelope: true,
+        })
+    }
+
+    fn to_raw(&self) -> MsgPackNativeBytes<'static> {
+        MsgPackNativeBytes(Cow::Owned(self.0.to_vec()))
+    }
+}
+
+/// Serializes as [`rmp_serde`] does, except that the bytes of a
+/// `MsgPackNativeBytes` are written as they are rather than as a byte string.
+/// Structs are written as arrays by hand, so that their fields come back
+/// through this serializer.
+struct NativeSerializer {
+    inner: rmp_serde::Serializer<Vec<u8>>,
+    raw: bool,
+}
+
+type Inner<'a> = &'a mut rmp_serde::Serializer<Vec<u8>>;
+
+macro_rules! forward_to_rmp_serializer {
+    ($($method:ident($($arg:ident: $ty:ty),*) -> $ok:ty;)*) => {$(
+        fn $method(self, $($arg: $ty),*) -> Result<$ok, Self::Error> {
+            (&mut self.inner).$method($($arg),*)
+        }
+    )*};
+}
+
+impl<'a> ser::Serializer for &'a mut NativeSerializer {
+    type Error = rmp_serde::encode::Error;
+    type Ok = ();
+    type SerializeMap = <Inner<'a> as ser::Serializer>::SerializeMap;
+    type SerializeSeq = <Inner<'a> as ser::Serializer>::SerializeSeq;
+    type SerializeStruct = NativeStruct<'a>;
+    type SerializeStructVariant = <Inner<'a> as ser::Serializer>::SerializeStructVariant;
+    type SerializeTuple = <Inner<'a> as ser::Serializer>::SerializeTuple;
+    type SerializeTupleStruct = <Inner<'a> as ser::Serializer>::SerializeTupleStruct;
+    type SerializeTupleVariant = <Inner<'a> as ser::Serializer>::SerializeTupleVariant;
+
+    forward_to_rmp_serializer! {
+        serialize_bool(v: bool) -> ();
+        serialize_i8(v: i8) -> ();
+        serialize_i16(v: i16) -> ();
+        serialize_i32(v: i32) -> ();
+        serialize_i64(v: i64) -> ();
+        serialize_u8(v: u8) -> ();
+        serialize_u16(v: u16) -> ();
+        serialize_u32(v: u32) -> ();
+        serialize_u64(v: u64) -> ();
+        serialize_f32(v: f32) -> ();
+        serialize_f64(v: f64) -> ();
+        serialize_char(v: char) -> ();
+        serialize_str(v: &str) -> ();
+        serialize_none() -> ();
+        serialize_unit() -> ();
+        serialize_unit_struct(name: &'static str) -> ();
+        serialize_unit_variant(name: &'static str, variant_index: u32, variant: &'static str) -> ();
+        serialize_seq(len: Option<usize>) -> Self::SerializeSeq;
+        serialize_tuple(len: usize) -> Self::SerializeTuple;
+        serialize_tuple_struct(name: &'static str, len: usize) -> Self::SerializeTupleStruct;
+        serialize_tuple_variant(
+            name: &'static str,
+            variant_index: u32,
+            variant: &'static str,
+            len: usize
+        ) -> Self::SerializeTupleVariant;
+        serialize_map(len: Option<usize>) -> Self::SerializeMap;
+        serialize_struct_variant(
+            name: &'static str,
+            variant_index: u32,
+            variant: &'static str,
+            len: usize
+        ) -> Self::SerializeStructVariant;
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<(), Self::Error> {
+        if self.raw {
+            // It would be read back as the nested layout of `MsgPackBytes`.
+            if let Some(0xc4..=0xc6) = v.first() {
+                return Err(ser::Error::custom(
+                    "data which is a MessagePack byte string cannot be written in the native \
+                     layout of `MsgPackNativeBytes`; use `MsgPackBytes`",
+                ));
+            }
+
+            self.inner.get_mut().extend_from_slice(v);
+            Ok(())
+        } else {
+            (&mut self.inner).serialize_bytes(v)
+        }
+    }
+
+    fn serialize_some<T: ?Sized + Serialize>(self, value: &T) -> Result<(), Self::Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_struct<T: ?Sized + Serialize>(
+        self,
+        name: &'static str,
+        value: &T,
+    ) -> Result<(), Self::Error> {
+        if name != NATIVE_TOKEN {
+            return (&mut self.inner).serialize_newtype_struct(name, value);
+        }
+
+        self.raw = true;
+        let result = value.serialize(&mut *self);
+        self.raw = false;
+        result
+    }
+
+    fn serialize_newtype_variant<T: ?Sized + Serialize>(
+        self,
+        name: &'static str,
+        variant_index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<(), Self::Error> {
+        (&mut self.inner).serialize_newtype_variant(name, variant_index, variant, value)
+    }
+
+    fn serialize_struct(
+        self,
+        _name: &'static str,
+        len: usize,
+    ) -> Result<Self::SerializeStruct, Self::Error> {
+        let output = self.inner.get_mut();
+        match u16::try_from(len) {
+            Ok(len @ 0..=15) => output.push(0x90 | len as u8),
+            Ok(len) => {
+                output.push(0xdc);
+                output.extend_from_slice(&len.to_be_bytes());
+            }
+            Err(_) => {
+                let len = u32::try_from(len).map_err(|_| {
+                    <rmp_serde::encode::Error as ser::Error>::custom("struct has too many fields")
+                })?;
+                output.push(0xdd);
+                output.extend_from_slice(&len.to_be_bytes());
+            }
+        }
+
+        Ok(NativeStruct(self))
+    }
+
+    fn is_human_readable(&self) -> bool {
+        false
+    }
+}
+
+struct NativeStruct<'a>(&'a mut NativeSerializer);
+
+impl SerializeStruct for NativeStruct<'_> {
+    type Error = rmp_serde::encode::Error;
+    type Ok = ();
+
+    fn serialize_field<T: ?Sized + Serialize>(
+        &mut self,
+        _key: &'static str,
+        value: &T,
+    ) -> Result<(), Self::Error> {
+        value.serialize(&mut *self.0)
+    }
+
+    fn end(self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+/// Deserializes with [`rmp_serde`], except that an envelope's fields are
+/// split out of the input without being parsed, so its data can be borrowed
+/// as a `MsgPackNativeBytes` in either layout. Only the top level is read as
+/// an envelope, so data is left to `rmp_serde` as a whole.
+struct NativeDeserializer<'de> {
+    input: &'de [u8],
+    envelope: bool,
+}
+
+macro_rules! forward_to_rmp_deserializer {
+    ($($method:ident($($arg:ident: $ty:ty),*);)*) => {$(
+        fn $method<V: Visitor<'de>>(self, $($arg: $ty,)* visitor: V) -> Result<V::Value, Self::Error> {
+            (&mut rmp_serde::Deserializer::from_read_ref(self.input)).$method($($arg,)* visitor)
+        }
+    )*};
+}
+
+impl<'de> de::Deserializer<'de> for NativeDeserializer<'de> {
+    type Error = rmp_serde::decode::Error;
+
+    forward_to_rmp_deserializer! {
+        deserialize_any();
+        deserialize_bool();
+        deserialize_i8();
+        deserialize_i16();
+        deserialize_i32();
+        deserialize_i64();
+        deserialize_u8();
+        deserialize_u16();
+        deserialize_u32();
+        deserialize_u64();
+        deserialize_f32();
+        deserialize_f64();
+        deserialize_char();
+        deserialize_str();
+        deserialize_string();
+        deserialize_bytes();
+        deserialize_byte_buf();
+        deserialize_option();
+        deserialize_unit();
+        deserialize_unit_struct(name: &'static str);
+        deserialize_seq();
+        deserialize_tuple(len: usize);
+        deserialize_tuple_struct(name: &'static str, len: usize);
+        deserialize_map();
+        deserialize_enum(name: &'static str, variants: &'static [&'static str]);
+        deserialize_identifier();
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        if name != NATIVE_TOKEN {
+            return (&mut rmp_serde::Deserializer::from_read_ref(self.input))
+                .deserialize_newtype_struct(name, visitor);
+        }
+
+        let header = Header::read(self.input)?;
+        match header.marker {
+            // The nested layout of `MsgPackBytes`.
+            0xc4..=0xc6 => visitor.visit_borrowed_bytes(header.payload(self.input)?),
+            _ => visitor.visit_borrowed_bytes(self.input),
+        }
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        if !self.envelope || name != "VersionedEnvelope" {
+            return (&mut rmp_serde::Deserializer::from_read_ref(self.input))
+                .deserialize_struct(name, fields, visitor);
+        }
+
+        let header = Header::read(self.input)?;
+        let fields = NativeFields {
+            input: &self.input[header.len..],
+            remaining: header.children,
+        };
+        match header.marker {
+            0x80..=0x8f | 0xde | 0xdf => visitor.visit_map(fields),
+            _ => visitor.visit_seq(fields),
+        }
+    }
+
+    /// Skips the value without parsing it, so that peeking at the version
+    /// costs nothing for the data.
+    fn deserialize_ignored_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        visitor.visit_unit()
+    }
+
+    fn is_human_readable(&self) -> bool {
+        false
+    }
+}
+
+/// The fields of an envelope, or their keys and values, each of which is
+/// split from the rest without being parsed. The last one is the rest of the
+/// input, so the data after the version is not even skipped over.
+struct NativeFields<'de> {
+    input: &'de [u8],
+    remaining: usize,
+}
+
+impl<'de> NativeFields<'de> {
+    fn next<T: DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> Result<T::Value, rmp_serde::decode::Error> {
+        self.remaining -= 1;
+        let len = if self.remaining == 0 {
+            self.input.len()
+        } else {
+            value_len(self.input)?
+        };
+        let (value, rest) = self.input.split_at(len);
+        self.input = rest;
+        seed.deserialize(NativeDeserializer {
+            input: value,
+            envelope: false,
+        })
+    }
+}
+
+impl<'de> SeqAccess<'de> for NativeFields<'de> {
+    type Error = rmp_serde::decode::Error;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> Result<Option<T::Value>, Self::Error> {
+        if self.remaining == 0 {
+            return Ok(None);
+        }
+
+        self.next(seed).map(Some)
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.remaining)
+    }
+}
+
+impl<'de> MapAccess<'de> for NativeFields<'de> {
+    type Error = rmp_serde::decode::Error;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, Self::Error> {
+        if self.remaining == 0 {
+            return Ok(None);
+        }
+
+        self.next(seed).map(Some)
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(
+        &mut self,
+        seed: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.next(seed)
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.remaining / 2)
+    }
+}
+
+/// The length of the MessagePack value at the start of `input`.
+fn value_len(input: &[u8]) -> Result<usize, rmp_serde::decode::Error> {
+    let mut len = 0;
+    let mut pending = 1;
+    while pending > 0 {
+        let header = Header::read(input.get(len..).ok_or_else(unexpected_end)?)?;
+        len += header.len + header.payload_len;
+        pending = pending - 1 + header.children;
+    }
+
+    if len > input.len() {
+        return Err(unexpected_end());
+    }
+
+    Ok(len)
+}
+
+/// The marker of a MessagePack value and the sizes which follow from it.
+struct Header {
+    marker: u8,
+    /// The length of the marker and the size or type fields after it.
+    len: usize,
+    /// The length of the string, binary or extension bytes after the header.
+    payload_len: usize,
+    /// The number of values in an array, or keys and values in a map.
+    children: usize,
+}
+
+impl Header {
+    fn read(input: &[u8]) -> Result<Header, rmp_serde::decode::Error> {
+        let marker = *input.first().ok_or_else(unexpected_end)?;
+        let size = |width: usize| -> Result<usize, rmp_serde::decode::Error> {
+            let bytes = input.get(1..1 + width).ok_or_else(unexpected_end)?;
+            Ok(bytes
+                .iter()
+                .fold(0, |size, byte| size << 8 | usize::from(*byte)))
+        };
+        let header = |len, payload_len, children| Header {
+            marker,
+            len,
+            payload_len,
+            children,
+        };
+
+        Ok(match marker {
+            0x00..=0x7f | 0xc0 | 0xc2 | 0xc3 | 0xe0..=0xff => header(1, 0, 0),
+            0x80..=0x8f => header(1, 0, 2 * usize::from(marker & 0x0f)),
+            0x90..=0x9f => header(1, 0, usize::from(marker & 0x0f)),
+            0xa0..=0xbf => header(1, usize::from(marker & 0x1f), 0),
+            0xc4 | 0xd9 => header(2, size(1)?, 0),
+            0xc5 | 0xda => header(3, size(2)?, 0),
+            0xc6 | 0xdb => header(5, size(4)?, 0),
+            0xc7 => header(3, size(1)?, 0),
+            0xc8 => header(4, size(2)?, 0),
+            0xc9 => header(6, size(4)?, 0),
+            0xca => header(1, 4, 0),
+            0xcb => header(1, 8, 0),
+            0xcc..=0xcf => header(1, 1 << (marker - 0xcc), 0),
+            0xd0..=0xd3 => header(1, 1 << (marker - 0xd0), 0),
+            0xd4..=0xd8 => header(2, 1 << (marker - 0xd4), 0),
+            0xdc => header(3, 0, size(2)?),
+            0xdd => header(5, 0, size(4)?),
+            0xde => header(3, 0, 2 * size(2)?),
+            0xdf => header(5, 0, 2 * size(4)?),
+            0xc1 => return Err(de::Error::custom("reserved MessagePack marker 0xc1")),
+        })
+    }
+
+    /// The string, binary or extension bytes after the header.
+    fn payload<'de>(&self, input: &'de [u8]) -> Result<&'de [u8], rmp_serde::decode::Error> {
+        input
+            .get(self.len..self.len + self.payload_len)
+            .ok_or_else(unexpected_end)
+    }
+}
+
+fn unexpected_end() -> rmp_serde::decode::Error {
+    de::Error::custom("unexpected end of MessagePack data")
+}
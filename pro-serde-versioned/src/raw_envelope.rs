@@ -0,0 +1,88 @@
+use alloc::string::String;
+use core::any::type_name;
+use core::fmt;
+
+use serde::ser;
+
+use crate::Envelope;
+
+/// The version and undecoded data of a version which a versioned enum does
+/// not have, e.g. one written by a newer producer. Held by the enum's
+/// `#[versioned(unknown)]` variant, so that it can be written back out exactly
+/// as it was read. `R` is the raw data of the format it is read from and
+/// written to, e.g. [`serde_json::Value`]: reading or writing the enum with a
+/// format whose [`Raw`](crate::DeserializeFormat::Raw) is not `R` does not
+/// compile.
+#[derive(Debug, PartialEq, Clone)]
+pub struct RawEnvelope<R> {
+    pub version: RawVersion,
+    pub data: R,
+}
+
+/// A version read from an envelope, which the versioned enum may not have: a
+/// version number, or for enums whose versions are named with
+/// `#[versioned(version = "..")]`, a name.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum RawVersion {
+    Number(usize),
+    Name(String),
+}
+
+impl From<usize> for RawVersion {
+    fn from(version_number: usize) -> Self {
+        RawVersion::Number(version_number)
+    }
+}
+
+impl fmt::Display for RawVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RawVersion::Number(version_number) => write!(f, "{}", version_number),
+            RawVersion::Name(name) => write!(f, "{:?}", name),
+        }
+    }
+}
+
+impl<R> RawEnvelope<R> {
+    /// Wraps `data` in an envelope `En` with this version.
+    #[doc(hidden)]
+    pub fn to_envelope<T, En, Err>(&self, data: T) -> Result<En, Err>
+    where
+        En: Envelope<T>,
+        Err: ser::Error,
+    {
+        En::from_raw_version(&self.version, data).ok_or_else(|| {
+            ser::Error::custom(format_args!(
+                "cannot write unknown version {} to `{}`",
+                self.version,
+                type_name::<En>()
+            ))
+        })
+    }
+}
+
+/// Keeps versions a versioned enum does not have, read from a format whose
+/// [`Raw`](crate::DeserializeFormat::Raw) data is `R`. Derived by
+/// [`VersionedDeserialize`](crate::VersionedDeserialize), for the `R` of the
+/// [`RawEnvelope`] held by the enum's `#[versioned(unknown)]` variant, or for
+/// every `R` if it has none.
+pub trait KeepsUnknown<R>: Sized {
+    /// The `#[versioned(unknown)]` variant holding `raw`, or `None` if the enum
+    /// has none.
+    fn keep_unknown(_raw: RawEnvelope<R>) -> Option<Self> {
+        None
+    }
+}
+
+/// Writes versions a versioned enum does not have back out, to a format whose
+/// [`Raw`](crate::SerializeFormat::Raw) data is `R`. Derived by
+/// [`VersionedSerialize`](crate::VersionedSerialize), for the `R` of the
+/// [`RawEnvelope`] held by the enum's `#[versioned(unknown)]` variant, or for
+/// every `R` if it has none.
+pub trait WritesUnknown<R> {
+    /// The [`RawEnvelope`] held by `self`, if it is the
+    /// `#[versioned(unknown)]` variant.
+    fn unknown(&self) -> Option<&RawEnvelope<R>> {
+        None
+    }
+}
@@ -0,0 +1,402 @@
+use std::collections::BTreeMap;
+use std::io::Write;
+
+use serde::ser::{self, Serialize, SerializeMap};
+use serde_json::{Error, Value};
+
+/// A serializer writing compact JSON byte for byte as `serde_json` writes the
+/// `serde_json::Value` the same data serializes to, without building the
+/// `Value`: each object's keys are sorted, as `Value` sorts them, and `f32`s
+/// are widened to `f64`, as `Value` holds them. Objects are held as the JSON
+/// of their entries until they end, to sort them; arrays and scalars are
+/// written as they are serialized.
+pub(crate) struct SortedJson<W> {
+    writer: W,
+}
+
+impl<W: Write> SortedJson<W> {
+    pub(crate) fn new(writer: W) -> Self {
+        SortedJson { writer }
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        self.writer.write_all(bytes).map_err(Error::io)
+    }
+
+    fn scalar<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        serde_json::to_writer(&mut self.writer, value)
+    }
+}
+
+impl<'a, W: Write> ser::Serializer for &'a mut SortedJson<W> {
+    type Error = Error;
+    type Ok = ();
+    type SerializeMap = Object<'a, W>;
+    type SerializeSeq = Array<'a, W>;
+    type SerializeStruct = Object<'a, W>;
+    type SerializeStructVariant = Object<'a, W>;
+    type SerializeTuple = Array<'a, W>;
+    type SerializeTupleStruct = Array<'a, W>;
+    type SerializeTupleVariant = Array<'a, W>;
+
+    fn serialize_bool(self, v: bool) -> Result<(), Error> {
+        self.scalar(&v)
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<(), Error> {
+        self.scalar(&v)
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<(), Error> {
+        self.scalar(&v)
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<(), Error> {
+        self.scalar(&v)
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<(), Error> {
+        self.scalar(&v)
+    }
+
+    fn serialize_i128(self, v: i128) -> Result<(), Error> {
+        self.scalar(&v)
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<(), Error> {
+        self.scalar(&v)
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<(), Error> {
+        self.scalar(&v)
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<(), Error> {
+        self.scalar(&v)
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<(), Error> {
+        self.scalar(&v)
+    }
+
+    fn serialize_u128(self, v: u128) -> Result<(), Error> {
+        self.scalar(&v)
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<(), Error> {
+        self.serialize_f64(f64::from(v))
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<(), Error> {
+        self.scalar(&v)
+    }
+
+    fn serialize_char(self, v: char) -> Result<(), Error> {
+        self.scalar(&v)
+    }
+
+    fn serialize_str(self, v: &str) -> Result<(), Error> {
+        self.scalar(v)
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<(), Error> {
+        self.scalar(v)
+    }
+
+    fn serialize_none(self) -> Result<(), Error> {
+        self.scalar(&())
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<(), Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<(), Error> {
+        self.scalar(&())
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<(), Error> {
+        self.scalar(&())
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+    ) -> Result<(), Error> {
+        self.scalar(variant)
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<(), Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<(), Error> {
+        let mut object = self.serialize_map(Some(1))?;
+        object.serialize_entry(variant, value)?;
+        object.end()
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Array<'a, W>, Error> {
+        self.write(b"[")?;
+        Ok(Array {
+            ser: self,
+            first: true,
+            in_variant: false,
+        })
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<Array<'a, W>, Error> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        len: usize,
+    ) -> Result<Array<'a, W>, Error> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        _len: usize,
+    ) -> Result<Array<'a, W>, Error> {
+        // The only key of the outer object needs no sorting.
+        self.write(b"{")?;
+        self.scalar(variant)?;
+        self.write(b":[")?;
+        Ok(Array {
+            ser: self,
+            first: true,
+            in_variant: true,
+        })
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Object<'a, W>, Error> {
+        Ok(Object {
+            ser: self,
+            entries: BTreeMap::new(),
+            next_key: None,
+            variant: None,
+        })
+    }
+
+    fn serialize_struct(self, _name: &'static str, len: usize) -> Result<Object<'a, W>, Error> {
+        self.serialize_map(Some(len))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        _len: usize,
+    ) -> Result<Object<'a, W>, Error> {
+        let mut object = self.serialize_map(None)?;
+        object.variant = Some(variant);
+        Ok(object)
+    }
+}
+
+pub(crate) struct Array<'a, W> {
+    ser: &'a mut SortedJson<W>,
+    first: bool,
+    in_variant: bool,
+}
+
+impl<'a, W: Write> Array<'a, W> {
+    fn element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        if !self.first {
+            self.ser.write(b",")?;
+        }
+        self.first = false;
+        value.serialize(&mut *self.ser)
+    }
+
+    fn end(self) -> Result<(), Error> {
+        self.ser.write(if self.in_variant { b"]}" } else { b"]" })
+    }
+}
+
+impl<'a, W: Write> ser::SerializeSeq for Array<'a, W> {
+    type Error = Error;
+    type Ok = ();
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<(), Error> {
+        Array::end(self)
+    }
+}
+
+impl<'a, W: Write> ser::SerializeTuple for Array<'a, W> {
+    type Error = Error;
+    type Ok = ();
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<(), Error> {
+        Array::end(self)
+    }
+}
+
+impl<'a, W: Write> ser::SerializeTupleStruct for Array<'a, W> {
+    type Error = Error;
+    type Ok = ();
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<(), Error> {
+        Array::end(self)
+    }
+}
+
+impl<'a, W: Write> ser::SerializeTupleVariant for Array<'a, W> {
+    type Error = Error;
+    type Ok = ();
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<(), Error> {
+        Array::end(self)
+    }
+}
+
+pub(crate) struct Object<'a, W> {
+    ser: &'a mut SortedJson<W>,
+    /// The JSON of each value, by key. Like the map of a `Value`, a repeated
+    /// key keeps the last value.
+    entries: BTreeMap<String, Vec<u8>>,
+    next_key: Option<String>,
+    /// The variant of a struct variant, which wraps the object in another.
+    variant: Option<&'static str>,
+}
+
+impl<'a, W: Write> Object<'a, W> {
+    fn entry<T: Serialize + ?Sized>(&mut self, key: String, value: &T) -> Result<(), Error> {
+        let mut json = SortedJson::new(Vec::new());
+        value.serialize(&mut json)?;
+        self.entries.insert(key, json.writer);
+        Ok(())
+    }
+
+    fn end(self) -> Result<(), Error> {
+        if let Some(variant) = self.variant {
+            self.ser.write(b"{")?;
+            self.ser.scalar(variant)?;
+            self.ser.write(b":")?;
+        }
+        self.ser.write(b"{")?;
+        for (index, (key, json)) in self.entries.iter().enumerate() {
+            if index > 0 {
+                self.ser.write(b",")?;
+            }
+            self.ser.scalar(key)?;
+            self.ser.write(b":")?;
+            self.ser.write(json)?;
+        }
+        self.ser.write(b"}")?;
+        if self.variant.is_some() {
+            self.ser.write(b"}")?;
+        }
+        Ok(())
+    }
+}
+
+impl<'a, W: Write> ser::SerializeMap for Object<'a, W> {
+    type Error = Error;
+    type Ok = ();
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), Error> {
+        self.next_key = Some(map_key(key)?);
+        Ok(())
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        let key = self
+            .next_key
+            .take()
+            .expect("serialize_value called before serialize_key");
+        self.entry(key, value)
+    }
+
+    fn end(self) -> Result<(), Error> {
+        Object::end(self)
+    }
+}
+
+impl<'a, W: Write> ser::SerializeStruct for Object<'a, W> {
+    type Error = Error;
+    type Ok = ();
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), Error> {
+        self.entry(key.to_owned(), value)
+    }
+
+    fn end(self) -> Result<(), Error> {
+        Object::end(self)
+    }
+}
+
+impl<'a, W: Write> ser::SerializeStructVariant for Object<'a, W> {
+    type Error = Error;
+    type Ok = ();
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), Error> {
+        self.entry(key.to_owned(), value)
+    }
+
+    fn end(self) -> Result<(), Error> {
+        Object::end(self)
+    }
+}
+
+/// The string `key` is written as, which `Value` decides, by writing it as the
+/// only key of a map.
+fn map_key<T: Serialize + ?Sized>(key: &T) -> Result<String, Error> {
+    struct OnlyKey<'a, T: ?Sized>(&'a T);
+
+    impl<'a, T: Serialize + ?Sized> Serialize for OnlyKey<'a, T> {
+        fn serialize<S: ser::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            let mut map = serializer.serialize_map(Some(1))?;
+            map.serialize_entry(self.0, &())?;
+            map.end()
+        }
+    }
+
+    match serde_json::to_value(OnlyKey(key))? {
+        Value::Object(map) => Ok(map.into_iter().next().expect("one key").0),
+        _ => unreachable!("a map is written as an object"),
+    }
+}
@@ -21,7 +21,16 @@ struct MyStructV3 {
     second_new_field: String,
 }
 
-#[derive(Debug, PartialEq, VersionedUpgrade, VersionedSerialize, VersionedDeserialize, Clone)]
+#[derive(
+    Debug,
+    PartialEq,
+    Versioned,
+    VersionedUpgrade,
+    VersionedDowngrade,
+    VersionedSerialize,
+    VersionedDeserialize,
+    Clone,
+)]
 enum MyStructVersion {
     V1(MyStructV1),
     V2(MyStructV2),
@@ -47,7 +56,42 @@ impl Upgrade<MyStructV3> for MyStructV2 {
     }
 }
 
-const V1_STRUCT: &'static str = r#"
+impl Downgrade<MyStructV2> for MyStructV3 {
+    fn downgrade(self: MyStructV3) -> MyStructV2 {
+        MyStructV2 {
+            field1: self.field1,
+            new_field: self.new_field,
+        }
+    }
+}
+
+impl Downgrade<MyStructV1> for MyStructV2 {
+    fn downgrade(self: MyStructV2) -> MyStructV1 {
+        MyStructV1 {
+            field1: self.field1.to_lowercase(),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
+struct MyStructV1Ref<'a> {
+    field1: &'a str,
+}
+
+/// Asserts that `borrowed` points into `input` rather than at a copy of it.
+fn assert_borrowed_from(input: &[u8], borrowed: &str) {
+    assert!(input.as_ptr_range().contains(&borrowed.as_ptr()));
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+const V1_STRUCT: &str = r#"
     {"version_number": 1, "data": {"field1": "value1"}}
 "#;
 
@@ -71,6 +115,70 @@ fn test_json_serde() -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
+#[test]
+fn test_json_borrowed() -> Result<(), Box<dyn std::error::Error>> {
+    let value: serde_json::Value = serde_json::from_str(V1_STRUCT)?;
+
+    let envelope: VersionedEnvelope<MyStructV1Ref> = value.deserialize_format()?;
+    assert_eq!(envelope.data, MyStructV1Ref { field1: "value1" });
+    assert!(std::ptr::eq(
+        envelope.data.field1,
+        value["data"]["field1"].as_str().unwrap()
+    ));
+
+    let peeked: VersionedEnvelope<de::IgnoredAny> = value.deserialize_format()?;
+    assert_eq!(peeked.version_number, 1);
+
+    Ok(())
+}
+
+#[test]
+fn test_json_raw() -> Result<(), Box<dyn std::error::Error>> {
+    #[derive(Debug, VersionedSerialize, VersionedDeserialize, Clone)]
+    enum MyStructRawVersion {
+        V1(MyStructV1),
+        #[versioned(unknown)]
+        Unknown(RawEnvelope<JsonRaw<'static>>),
+    }
+
+    let input = r#"{"version_number":1,"data":{"field1":"value1"}}"#;
+    let json: JsonRaw = serde_json::from_str(input)?;
+
+    let wrapper = MyStructVersion::versioned_deserialize(&json)?;
+    assert_eq!(
+        wrapper,
+        MyStructVersion::V1(MyStructV1 {
+            field1: "value1".to_string()
+        })
+    );
+    assert_eq!(wrapper.versioned_serialize::<JsonRaw>()?.0.get(), input);
+
+    // The data is left as text borrowed from the input until it is decoded.
+    let envelope: VersionedEnvelope<JsonRaw> = json.deserialize_format()?;
+    assert_eq!(envelope.version_number, 1);
+    assert_eq!(envelope.data.0.get(), r#"{"field1":"value1"}"#);
+
+    let data: MyStructV1Ref = envelope.data.deserialize_format()?;
+    assert_eq!(data, MyStructV1Ref { field1: "value1" });
+    assert_borrowed_from(input.as_bytes(), data.field1);
+
+    // Unknown versions are written back out as the exact text they were read
+    // from.
+    let newer = r#"{"version_number":7,"data":{ "b": [1, 2],  "a": null }}"#;
+    let newer_json: JsonRaw = serde_json::from_str(newer)?;
+    let unknown = MyStructRawVersion::versioned_deserialize(&newer_json)?;
+    assert!(matches!(
+        unknown,
+        MyStructRawVersion::Unknown(RawEnvelope {
+            version: RawVersion::Number(7),
+            ..
+        })
+    ));
+    assert_eq!(unknown.versioned_serialize::<JsonRaw>()?.0.get(), newer);
+
+    Ok(())
+}
+
 #[test]
 fn test_msgpack_serde() -> Result<(), Box<dyn std::error::Error>> {
     let json_value: serde_json::Value = serde_json::from_str(V1_STRUCT)?;
@@ -100,6 +208,358 @@ fn test_msgpack_serde() -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
+#[cfg(feature = "serde_cbor")]
+#[test]
+fn test_cbor_serde() -> Result<(), Box<dyn std::error::Error>> {
+    let json_value: serde_json::Value = serde_json::from_str(V1_STRUCT)?;
+    let versioned_struct: MyStructVersion = MyStructVersion::versioned_deserialize(&json_value)?;
+    let serialized_wrapper: CborBytes = MyStructVersion::versioned_serialize(&versioned_struct)?;
+
+    assert_eq!(
+        hex(&serialized_wrapper.0),
+        "a2 6e 76 65 72 73 69 6f 6e 5f 6e 75 6d 62 65 72 01 64 64 61 74 61 4f a1 66 66 69 65 6c \
+         64 31 66 76 61 6c 75 65 31"
+    );
+
+    // Asserting that serializer is symmetric
+    assert_eq!(
+        MyStructVersion::versioned_deserialize(&serialized_wrapper)?,
+        versioned_struct
+    );
+
+    // The data is borrowed straight from the input, and so are its strings.
+    let envelope: VersionedEnvelope<CborBytes> = serialized_wrapper.deserialize_format()?;
+    assert_eq!(envelope.version_number, 1);
+    assert!(matches!(envelope.data.0, Cow::Borrowed(_)));
+
+    let data: MyStructV1Ref = envelope.data.deserialize_format()?;
+    assert_eq!(data, MyStructV1Ref { field1: "value1" });
+    assert_borrowed_from(&serialized_wrapper.0, data.field1);
+
+    Ok(())
+}
+
+#[cfg(feature = "bincode")]
+#[test]
+fn test_bincode() -> Result<(), Box<dyn std::error::Error>> {
+    #[derive(Debug, VersionedSerialize, VersionedDeserialize, Clone)]
+    enum MyStructBincodeVersion {
+        V1(MyStructV1),
+        #[versioned(unknown)]
+        Unknown(RawEnvelope<BincodeBytes<'static>>),
+    }
+
+    #[derive(Debug, PartialEq, VersionedSerialize, VersionedDeserialize, Clone)]
+    #[versioned(repr = u8)]
+    enum MyStructByteVersion {
+        V1(MyStructV1),
+    }
+
+    #[derive(Debug, VersionedSerialize, Clone)]
+    enum MyStructNamedVersion {
+        #[versioned(version = "2024-03")]
+        March(MyStructV2),
+    }
+
+    let v1 = MyStructVersion::V1(MyStructV1 {
+        field1: "value1".to_string(),
+    });
+    let serialized_wrapper: BincodeBytes = v1.versioned_serialize()?;
+    assert_eq!(
+        hex(&serialized_wrapper.0),
+        "01 00 00 00 00 00 00 00 06 00 00 00 00 00 00 00 76 61 6c 75 65 31"
+    );
+
+    // The header is as wide as the `repr` of the version number.
+    let byte_v1 = MyStructByteVersion::V1(MyStructV1 {
+        field1: "value1".to_string(),
+    });
+    let byte_wrapper: BincodeBytes = byte_v1.versioned_serialize()?;
+    assert_eq!(
+        hex(&byte_wrapper.0),
+        "01 06 00 00 00 00 00 00 00 76 61 6c 75 65 31"
+    );
+    assert_eq!(
+        MyStructByteVersion::versioned_deserialize(&byte_wrapper)?,
+        byte_v1
+    );
+    let envelope: VersionedEnvelope<BincodeBytes, u8> = byte_wrapper.deserialize_format()?;
+    assert_eq!(envelope.version_number, 1);
+    assert!(
+        MyStructByteVersion::versioned_deserialize(&BincodeBytes(Cow::Borrowed(&[])))
+            .unwrap_err()
+            .to_string()
+            .contains("shorter than its header")
+    );
+
+    // Every version can be read back, and upgraded, without knowing in
+    // advance which one was written.
+    let v2 = MyStructVersion::V2(MyStructV2 {
+        field1: "value2".to_string(),
+        new_field: "new2".to_string(),
+    });
+    let v3 = MyStructVersion::V3(MyStructV3 {
+        field1: "value3".to_string(),
+        new_field: "new3".to_string(),
+        second_new_field: "second3".to_string(),
+    });
+    let written = [&v2, &v1, &v3]
+        .into_iter()
+        .map(|wrapper| wrapper.versioned_serialize::<BincodeBytes>())
+        .collect::<Result<Vec<_>, _>>()?;
+    let read = written
+        .iter()
+        .map(MyStructVersion::versioned_deserialize)
+        .collect::<Result<Vec<_>, _>>()?;
+    assert_eq!(read, [v2, v1, v3.clone()]);
+    assert_eq!(
+        read.into_iter()
+            .map(|wrapper| wrapper.upgrade_to_latest().field1)
+            .collect::<Vec<_>>(),
+        ["value2", "VALUE1", "value3"]
+    );
+
+    // The version can be read from the header without knowing the type of
+    // the data, which is borrowed straight from the input.
+    let envelope: VersionedEnvelope<de::IgnoredAny> = written[2].deserialize_format()?;
+    assert_eq!(envelope.version_number, 3);
+
+    let envelope: VersionedEnvelope<BincodeBytes> = serialized_wrapper.deserialize_format()?;
+    assert_eq!(envelope.version_number, 1);
+    assert!(matches!(envelope.data.0, Cow::Borrowed(_)));
+
+    let data: MyStructV1Ref = envelope.data.deserialize_format()?;
+    assert_eq!(data, MyStructV1Ref { field1: "value1" });
+    assert_borrowed_from(&serialized_wrapper.0, data.field1);
+
+    // Unknown versions are written back out as the exact bytes they were read
+    // from.
+    let unknown = MyStructBincodeVersion::versioned_deserialize(&written[2])?;
+    assert!(matches!(
+        unknown,
+        MyStructBincodeVersion::Unknown(RawEnvelope {
+            version: RawVersion::Number(3),
+            ..
+        })
+    ));
+    assert_eq!(unknown.versioned_serialize::<BincodeBytes>()?, written[2]);
+
+    // The header only holds version numbers.
+    let named = MyStructNamedVersion::March(MyStructV2 {
+        field1: "value1".to_string(),
+        new_field: "value2".to_string(),
+    });
+    assert_eq!(
+        named
+            .versioned_serialize::<BincodeBytes>()
+            .unwrap_err()
+            .to_string(),
+        "a `BincodeBytes` envelope holds a version number, not a name"
+    );
+
+    Ok(())
+}
+
+#[cfg(feature = "postcard")]
+#[test]
+fn test_postcard() -> Result<(), Box<dyn std::error::Error>> {
+    let v1 = MyStructVersion::V1(MyStructV1 {
+        field1: "value1".to_string(),
+    });
+    let serialized_wrapper: PostcardBytes = v1.versioned_serialize()?;
+    assert_eq!(hex(&serialized_wrapper.0), "01 07 06 76 61 6c 75 65 31");
+
+    let v3 = MyStructVersion::V3(MyStructV3 {
+        field1: "value3".to_string(),
+        new_field: "new3".to_string(),
+        second_new_field: "second3".to_string(),
+    });
+    let written = [&v3, &v1]
+        .into_iter()
+        .map(|wrapper| wrapper.versioned_serialize::<PostcardBytes>())
+        .collect::<Result<Vec<_>, _>>()?;
+    let read = written
+        .iter()
+        .map(MyStructVersion::versioned_deserialize)
+        .collect::<Result<Vec<_>, _>>()?;
+    assert_eq!(read, [v3, v1]);
+
+    // The data is borrowed straight from the input, and so are its strings.
+    let envelope: VersionedEnvelope<PostcardBytes> = serialized_wrapper.deserialize_format()?;
+    assert_eq!(envelope.version_number, 1);
+    assert!(matches!(envelope.data.0, Cow::Borrowed(_)));
+
+    let data: MyStructV1Ref = envelope.data.deserialize_format()?;
+    assert_eq!(data, MyStructV1Ref { field1: "value1" });
+    assert_borrowed_from(&serialized_wrapper.0, data.field1);
+
+    Ok(())
+}
+
+#[test]
+fn test_msgpack_named() -> Result<(), Box<dyn std::error::Error>> {
+    #[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+    struct MyStructV2Reordered {
+        new_field: String,
+        field1: String,
+    }
+
+    #[derive(Debug, PartialEq, VersionedSerialize, VersionedDeserialize, Clone)]
+    enum MyStructReorderedVersion {
+        V1(MyStructV1),
+        V2(MyStructV2Reordered),
+    }
+
+    let versioned_struct = MyStructVersion::V1(MyStructV1 {
+        field1: "value1".to_string(),
+    });
+    let named: MsgPackNamedBytes = versioned_struct.versioned_serialize()?;
+    assert_eq!(
+        hex(&named.0),
+        "82 ae 76 65 72 73 69 6f 6e 5f 6e 75 6d 62 65 72 01 a4 64 61 74 61 c4 0f 81 a6 66 69 65 \
+         6c 64 31 a6 76 61 6c 75 65 31"
+    );
+    assert_eq!(
+        MyStructVersion::versioned_deserialize(&named)?,
+        versioned_struct
+    );
+
+    // Fields are matched by name, so they can be reordered.
+    let versioned_struct = MyStructVersion::V2(MyStructV2 {
+        field1: "value1".to_string(),
+        new_field: "value2".to_string(),
+    });
+    let named: MsgPackNamedBytes = versioned_struct.versioned_serialize()?;
+    assert_eq!(
+        MyStructReorderedVersion::versioned_deserialize(&named)?,
+        MyStructReorderedVersion::V2(MyStructV2Reordered {
+            new_field: "value2".to_string(),
+            field1: "value1".to_string(),
+        })
+    );
+
+    // Either struct encoding can be read by either format.
+    let positional: MsgPackBytes = versioned_struct.versioned_serialize()?;
+    assert_eq!(
+        MyStructVersion::versioned_deserialize(&MsgPackNamedBytes(positional.0))?,
+        versioned_struct
+    );
+    assert_eq!(
+        MyStructVersion::versioned_deserialize(&MsgPackBytes(named.0))?,
+        versioned_struct
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_msgpack_native() -> Result<(), Box<dyn std::error::Error>> {
+    #[derive(Debug, VersionedSerialize, VersionedDeserialize, Clone)]
+    enum MyStructNativeVersion {
+        V1(MyStructV1),
+        #[versioned(unknown)]
+        Unknown(RawEnvelope<MsgPackNativeBytes<'static>>),
+    }
+
+    let versioned_struct = MyStructVersion::V1(MyStructV1 {
+        field1: "value1".to_string(),
+    });
+    let native: MsgPackNativeBytes = versioned_struct.versioned_serialize()?;
+    assert_eq!(hex(&native.0), "92 01 91 a6 76 61 6c 75 65 31");
+    assert_eq!(
+        MyStructVersion::versioned_deserialize(&native)?,
+        versioned_struct
+    );
+
+    let mut streamed = Vec::new();
+    versioned_struct.versioned_serialize_into(&mut rmp_serde::Serializer::new(&mut streamed))?;
+    assert_eq!(streamed, native.0.as_ref());
+
+    // Generic MessagePack tools can read the whole envelope.
+    let envelope: VersionedEnvelope<MyStructV1> = rmp_serde::from_slice(&native.0)?;
+    assert_eq!(envelope.data.field1, "value1");
+
+    // The version can be peeked at without the data, which is borrowed
+    // straight from the input until it is decoded.
+    let envelope: VersionedEnvelope<de::IgnoredAny> = native.deserialize_format()?;
+    assert_eq!(envelope.version_number, 1);
+
+    let envelope: VersionedEnvelope<MsgPackNativeBytes> = native.deserialize_format()?;
+    assert_eq!(hex(&envelope.data.0), "91 a6 76 61 6c 75 65 31");
+    assert!(matches!(envelope.data.0, Cow::Borrowed(_)));
+
+    let data: MyStructV1Ref = envelope.data.deserialize_format()?;
+    assert_eq!(data, MyStructV1Ref { field1: "value1" });
+    assert_borrowed_from(&native.0, data.field1);
+
+    // The nested layout of `MsgPackBytes` is read too.
+    let nested: MsgPackBytes = versioned_struct.versioned_serialize()?;
+    let nested = MsgPackNativeBytes(nested.0);
+    assert_eq!(
+        MyStructVersion::versioned_deserialize(&nested)?,
+        versioned_struct
+    );
+    let envelope: VersionedEnvelope<MsgPackNativeBytes> = nested.deserialize_format()?;
+    assert_eq!(hex(&envelope.data.0), "91 a6 76 61 6c 75 65 31");
+
+    // Unknown versions are written back out as the exact bytes they were read
+    // from.
+    let newer = MsgPackNativeBytes(Cow::Borrowed(&[
+        0x92, 0x07, 0x92, 0xa1, 0x62, 0x92, 0x01, 0xcd, 0x01, 0x00,
+    ]));
+    let unknown = MyStructNativeVersion::versioned_deserialize(&newer)?;
+    assert!(matches!(
+        unknown,
+        MyStructNativeVersion::Unknown(RawEnvelope {
+            version: RawVersion::Number(7),
+            ..
+        })
+    ));
+    assert_eq!(unknown.versioned_serialize::<MsgPackNativeBytes>()?, newer);
+
+    Ok(())
+}
+
+#[test]
+fn test_msgpack_native_byte_strings() -> Result<(), Box<dyn std::error::Error>> {
+    #[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+    struct Blob {
+        #[serde(with = "serde_bytes")]
+        bytes: Vec<u8>,
+    }
+
+    #[derive(Debug, PartialEq, VersionedSerialize, VersionedDeserialize, Clone)]
+    enum BlobVersion {
+        V1(serde_bytes::ByteBuf),
+        V2(Blob),
+    }
+
+    // Byte strings inside the data round-trip in the native layout.
+    let blob = BlobVersion::V2(Blob {
+        bytes: vec![0xc4, 0x01, 0x02],
+    });
+    let native: MsgPackNativeBytes = blob.versioned_serialize()?;
+    assert_eq!(BlobVersion::versioned_deserialize(&native)?, blob);
+
+    // Data which is itself a byte string would be read back as the nested
+    // layout, so it is only written by `MsgPackBytes`, and read in its layout.
+    let bytes = BlobVersion::V1(serde_bytes::ByteBuf::from(vec![0x91, 0x01]));
+    assert!(bytes
+        .versioned_serialize::<MsgPackNativeBytes>()
+        .unwrap_err()
+        .to_string()
+        .contains("cannot be written in the native layout"));
+
+    let nested: MsgPackBytes = bytes.versioned_serialize()?;
+    assert_eq!(BlobVersion::versioned_deserialize(&nested)?, bytes);
+    assert_eq!(
+        BlobVersion::versioned_deserialize(&MsgPackNativeBytes(nested.0))?,
+        bytes
+    );
+
+    Ok(())
+}
+
 #[test]
 fn test_change_field_representation() -> Result<(), Box<dyn std::error::Error>> {
     #[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
@@ -270,3 +730,1647 @@ fn test_change_enum_values() -> Result<(), Box<dyn std::error::Error>> {
     assert_eq!(v2_struct, MyStructVersion2::V2(MyStructV2 { field1: 123 }));
     Ok(())
 }
+
+#[test]
+fn test_explicit_version_numbers() -> Result<(), Box<dyn std::error::Error>> {
+    #[derive(
+        Debug, PartialEq, VersionedUpgrade, VersionedSerialize, VersionedDeserialize, Clone,
+    )]
+    enum MyStructVersion {
+        #[versioned(version = 1)]
+        Legacy(MyStructV1),
+        #[versioned(version = 2)]
+        Current(MyStructV2),
+    }
+
+    let value: serde_json::Value = serde_json::from_str(V1_STRUCT)?;
+    let legacy = MyStructVersion::versioned_deserialize(&value)?;
+
+    assert_eq!(
+        legacy,
+        MyStructVersion::Legacy(MyStructV1 {
+            field1: "value1".to_string()
+        })
+    );
+
+    let current = MyStructVersion::Current(legacy.upgrade_to_latest());
+    let serialized_wrapper: serde_json::Value = current.versioned_serialize()?;
+
+    assert_eq!(
+        serialized_wrapper,
+        serde_json::json!({
+            "version_number": 2,
+            "data": {
+                "field1": "VALUE1",
+                "new_field": "default_value"
+            }
+        })
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_explicit_version_numbers_mixed_with_names() -> Result<(), Box<dyn std::error::Error>> {
+    #[derive(Debug, PartialEq, VersionedSerialize, VersionedDeserialize, Clone)]
+    enum MyStructVersion {
+        V1(MyStructV1),
+        #[versioned(version = 7)]
+        Beta(MyStructV2),
+    }
+
+    let beta = MyStructVersion::Beta(MyStructV2 {
+        field1: "value1".to_string(),
+        new_field: "value2".to_string(),
+    });
+
+    let serialized_wrapper: serde_json::Value = beta.versioned_serialize()?;
+
+    assert_eq!(serialized_wrapper["version_number"], 7);
+    assert_eq!(
+        MyStructVersion::versioned_deserialize(&serialized_wrapper)?,
+        beta
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_version_names() -> Result<(), Box<dyn std::error::Error>> {
+    #[derive(
+        Debug,
+        PartialEq,
+        Versioned,
+        VersionedUpgrade,
+        VersionedDowngrade,
+        VersionedSerialize,
+        VersionedDeserialize,
+        Clone,
+    )]
+    enum MyStructVersion {
+        V1(MyStructV1),
+        #[versioned(version = "2024-03")]
+        March(MyStructV2),
+        #[versioned(version = "2024-06")]
+        June(MyStructV3),
+    }
+
+    let value: serde_json::Value = serde_json::from_str(V1_STRUCT)?;
+    let june =
+        MyStructVersion::June(MyStructVersion::versioned_deserialize(&value)?.upgrade_to_latest());
+    let serialized_wrapper: serde_json::Value = june.versioned_serialize()?;
+
+    assert_eq!(
+        serialized_wrapper,
+        serde_json::json!({
+            "version_number": "2024-06",
+            "data": {
+                "field1": "VALUE1",
+                "new_field": "default_value",
+                "second_new_field": "default_value_v3"
+            }
+        })
+    );
+    assert_eq!(
+        MyStructVersion::versioned_deserialize(&serialized_wrapper)?,
+        june
+    );
+
+    // Named versions are numbered after the variant declared before them.
+    assert_eq!(<MyStructV2 as VersionOf<MyStructVersion>>::VERSION, 2);
+    assert_eq!(<MyStructV3 as VersionOf<MyStructVersion>>::VERSION, 3);
+
+    let march = MyStructVersion::March(MyStructV2 {
+        field1: "value1".to_string(),
+        new_field: "value2".to_string(),
+    });
+    let msgpack: MsgPackBytes = march.versioned_serialize()?;
+
+    assert_eq!(MyStructVersion::versioned_deserialize(&msgpack)?, march);
+
+    for version in [serde_json::json!("2024-09"), serde_json::json!(3)] {
+        let unknown = serde_json::json!({ "version_number": version, "data": {} });
+        assert!(MyStructVersion::versioned_deserialize(&unknown).is_err());
+    }
+
+    // Versions can be upgraded and downgraded to by name, without knowing the
+    // numbers they were given.
+    let v1 = MyStructVersion::V1(MyStructV1 {
+        field1: "value1".to_string(),
+    });
+    assert_eq!(
+        v1.clone().upgrade_to_named_version("2024-03")?,
+        v1.clone().upgrade_to_version(2)?
+    );
+    assert!(matches!(
+        v1.upgrade_to_named_version("2024-09"),
+        Err(UpgradeError::UnknownVersionName { name }) if name == "2024-09"
+    ));
+
+    assert_eq!(
+        june.clone().downgrade_to_named_version("2024-03")?,
+        june.clone().downgrade_to_version(2)?
+    );
+    let serialized_wrapper: serde_json::Value = june.serialize_as_named_version("2024-03")?;
+    assert_eq!(serialized_wrapper["version_number"], "2024-03");
+    assert_eq!(
+        june.downgrade_to_named_version("2024-09"),
+        Err(DowngradeError::UnknownVersionName {
+            name: "2024-09".to_string()
+        })
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_version_repr() -> Result<(), Box<dyn std::error::Error>> {
+    #[derive(Debug, PartialEq, VersionedSerialize, VersionedDeserialize, Clone)]
+    #[versioned(repr = u8)]
+    enum MyStructVersion {
+        V1(MyStructV1),
+        V2(MyStructV2),
+    }
+
+    let v2 = MyStructVersion::V2(MyStructV2 {
+        field1: "value1".to_string(),
+        new_field: "value2".to_string(),
+    });
+
+    let serialized_wrapper: serde_json::Value = v2.versioned_serialize()?;
+    let envelope: VersionedEnvelope<serde_json::Value, u8> =
+        serde_json::from_value(serialized_wrapper.clone())?;
+
+    assert_eq!(envelope.version_number, 2u8);
+    assert_eq!(
+        MyStructVersion::versioned_deserialize(&serialized_wrapper)?,
+        v2
+    );
+
+    let msgpack: MsgPackBytes = v2.versioned_serialize()?;
+    assert_eq!(MyStructVersion::versioned_deserialize(&msgpack)?, v2);
+
+    let out_of_range = serde_json::json!({ "version_number": 258, "data": {} });
+    let error = MyStructVersion::versioned_deserialize(&out_of_range).unwrap_err();
+    assert!(error.to_string().contains("u8"), "{}", error);
+
+    Ok(())
+}
+
+#[test]
+fn test_named_envelope_version_repr() -> Result<(), Box<dyn std::error::Error>> {
+    #[derive(Debug, PartialEq, VersionedSerialize, VersionedDeserialize, Clone)]
+    #[versioned(tag = "v", repr = u16)]
+    enum MyStructVersion {
+        V1(MyStructV1),
+    }
+
+    let v1 = MyStructVersion::V1(MyStructV1 {
+        field1: "value1".to_string(),
+    });
+
+    let serialized_wrapper: serde_json::Value = v1.versioned_serialize()?;
+    assert_eq!(serialized_wrapper["v"], 1);
+    assert_eq!(
+        MyStructVersion::versioned_deserialize(&serialized_wrapper)?,
+        v1
+    );
+
+    let out_of_range = serde_json::json!({ "v": 65_537, "data": {} });
+    let error = MyStructVersion::versioned_deserialize(&out_of_range).unwrap_err();
+    assert!(error.to_string().contains("u16"), "{}", error);
+
+    Ok(())
+}
+
+#[test]
+fn test_unversioned_version_name() -> Result<(), Box<dyn std::error::Error>> {
+    #[derive(Debug, PartialEq, VersionedSerialize, VersionedDeserialize, Clone)]
+    #[versioned(unversioned = "legacy")]
+    enum MyStructVersion {
+        #[versioned(version = "legacy")]
+        Legacy(MyStructV1),
+        #[versioned(version = "current")]
+        Current(MyStructV2),
+    }
+
+    let v1_struct = MyStructV1 {
+        field1: "value1".to_string(),
+    };
+
+    assert_eq!(
+        MyStructVersion::versioned_deserialize(&serde_json::to_value(&v1_struct)?)?,
+        MyStructVersion::Legacy(v1_struct.clone())
+    );
+    let serialized_wrapper: serde_json::Value =
+        MyStructVersion::Legacy(v1_struct).versioned_serialize()?;
+
+    assert_eq!(serialized_wrapper["version_number"], "legacy");
+
+    Ok(())
+}
+
+mod without_imports {
+    #[derive(serde::Serialize, serde::Deserialize, Debug, PartialEq, Clone)]
+    pub struct MyStructV1(pub String);
+
+    #[derive(
+        pro_serde_versioned::VersionedUpgrade,
+        pro_serde_versioned::VersionedSerialize,
+        pro_serde_versioned::VersionedDeserialize,
+        Debug,
+        PartialEq,
+        Clone,
+    )]
+    pub enum MyStructVersion {
+        V1(MyStructV1),
+    }
+}
+
+#[test]
+fn test_derive_without_imports() -> Result<(), Box<dyn std::error::Error>> {
+    let v1_struct =
+        without_imports::MyStructVersion::V1(without_imports::MyStructV1("value1".to_string()));
+
+    let serialized_wrapper: serde_json::Value = v1_struct.versioned_serialize()?;
+
+    assert_eq!(
+        without_imports::MyStructVersion::versioned_deserialize(&serialized_wrapper)?,
+        v1_struct
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_try_upgrade_to_latest() -> Result<(), Box<dyn std::error::Error>> {
+    let v1_struct = MyStructVersion::V1(MyStructV1 {
+        field1: "value1".to_string(),
+    });
+
+    assert_eq!(
+        v1_struct.clone().try_upgrade_to_latest()?,
+        v1_struct.upgrade_to_latest()
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_fallible_upgrade() -> Result<(), Box<dyn std::error::Error>> {
+    #[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+    struct MyStructV1 {
+        field1: String,
+    }
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+    struct MyStructV2 {
+        field1: u32,
+    }
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+    struct MyStructV3 {
+        field1: u32,
+        field2: bool,
+    }
+
+    impl TryUpgrade<MyStructV2> for MyStructV1 {
+        type Error = std::num::ParseIntError;
+
+        fn try_upgrade(self: MyStructV1) -> Result<MyStructV2, Self::Error> {
+            Ok(MyStructV2 {
+                field1: self.field1.parse()?,
+            })
+        }
+    }
+
+    impl Upgrade<MyStructV3> for MyStructV2 {
+        fn upgrade(self: MyStructV2) -> MyStructV3 {
+            MyStructV3 {
+                field1: self.field1,
+                field2: false,
+            }
+        }
+    }
+
+    #[derive(Debug, PartialEq, VersionedUpgrade, Clone)]
+    #[versioned(fallible)]
+    enum MyStructVersion {
+        V1(MyStructV1),
+        V2(MyStructV2),
+        V3(MyStructV3),
+    }
+
+    let v3_struct = MyStructVersion::V1(MyStructV1 {
+        field1: "123".to_string(),
+    })
+    .try_upgrade_to_latest()?;
+
+    assert_eq!(v3_struct, MyStructV3 {
+        field1: 123,
+        field2: false
+    });
+
+    let error = MyStructVersion::V1(MyStructV1 {
+        field1: "value1".to_string(),
+    })
+    .try_upgrade_to_latest()
+    .unwrap_err();
+
+    assert!(matches!(error, UpgradeError::Step { from: 1, to: 2, .. }));
+    assert_eq!(
+        error.to_string(),
+        "Failed to upgrade from version 1 to version 2: invalid digit found in string"
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_downgrade_to_version() -> Result<(), Box<dyn std::error::Error>> {
+    let v3_struct = MyStructVersion::V3(MyStructV3 {
+        field1: "VALUE1".to_string(),
+        new_field: "value2".to_string(),
+        second_new_field: "value3".to_string(),
+    });
+
+    assert_eq!(
+        v3_struct.clone().downgrade_to_version(2)?,
+        MyStructVersion::V2(MyStructV2 {
+            field1: "VALUE1".to_string(),
+            new_field: "value2".to_string(),
+        })
+    );
+
+    assert_eq!(
+        v3_struct.clone().downgrade_to_version(3)?,
+        v3_struct.clone()
+    );
+
+    let serialized_wrapper: serde_json::Value = v3_struct.serialize_as_version(1)?;
+    let value: serde_json::Value = serde_json::from_str(V1_STRUCT)?;
+    assert_eq!(serialized_wrapper, value);
+
+    Ok(())
+}
+
+#[test]
+fn test_downgrade_to_invalid_version() {
+    let v2_struct = MyStructVersion::V2(MyStructV2 {
+        field1: "value1".to_string(),
+        new_field: "value2".to_string(),
+    });
+
+    assert_eq!(
+        v2_struct.clone().downgrade_to_version(3),
+        Err(DowngradeError::NewerVersion {
+            current: 2,
+            target: 3
+        })
+    );
+
+    assert_eq!(
+        v2_struct.clone().downgrade_to_version(4),
+        Err(DowngradeError::UnknownVersion { version: 4 })
+    );
+
+    let serialized_wrapper: Result<serde_json::Value, _> = v2_struct.serialize_as_version(0);
+    assert_eq!(
+        serialized_wrapper.unwrap_err().to_string(),
+        "Cannot downgrade to unknown version 0"
+    );
+}
+
+#[test]
+fn test_upgrade_to_version() -> Result<(), Box<dyn std::error::Error>> {
+    let v1_struct = MyStructVersion::V1(MyStructV1 {
+        field1: "value1".to_string(),
+    });
+
+    assert_eq!(
+        v1_struct.clone().upgrade_to_version(2)?,
+        MyStructVersion::V2(MyStructV2 {
+            field1: "VALUE1".to_string(),
+            new_field: "default_value".to_string(),
+        })
+    );
+
+    assert_eq!(v1_struct.clone().upgrade_to_version(1)?, v1_struct.clone());
+
+    assert_eq!(v1_struct.clone().upgrade_to::<MyStructV2>()?, MyStructV2 {
+        field1: "VALUE1".to_string(),
+        new_field: "default_value".to_string(),
+    });
+
+    assert_eq!(
+        v1_struct.clone().upgrade_to::<MyStructV3>()?,
+        v1_struct.upgrade_to_latest()
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_upgrade_to_invalid_version() {
+    let v2_struct = MyStructVersion::V2(MyStructV2 {
+        field1: "value1".to_string(),
+        new_field: "value2".to_string(),
+    });
+
+    assert!(matches!(
+        v2_struct.clone().upgrade_to_version(1),
+        Err(UpgradeError::OlderVersion {
+            current: 2,
+            target: 1
+        })
+    ));
+
+    assert!(matches!(
+        v2_struct.clone().upgrade_to::<MyStructV1>(),
+        Err(UpgradeError::OlderVersion {
+            current: 2,
+            target: 1
+        })
+    ));
+
+    assert!(matches!(
+        v2_struct.upgrade_to_version(4),
+        Err(UpgradeError::UnknownVersion { version: 4 })
+    ));
+}
+
+#[test]
+fn test_upgrade_to_with_only_versioned_upgrade() -> Result<(), Box<dyn std::error::Error>> {
+    #[derive(Debug, PartialEq, Clone)]
+    struct MyStructV1(u32);
+
+    #[derive(Debug, PartialEq, Clone)]
+    struct MyStructV2(u64);
+
+    impl Upgrade<MyStructV2> for MyStructV1 {
+        fn upgrade(self: MyStructV1) -> MyStructV2 {
+            MyStructV2(self.0.into())
+        }
+    }
+
+    #[derive(Debug, PartialEq, Versioned, VersionedUpgrade, Clone)]
+    enum MyStructVersion {
+        V1(MyStructV1),
+        V2(MyStructV2),
+    }
+
+    assert_eq!(<MyStructV2 as VersionOf<MyStructVersion>>::VERSION, 2);
+    assert_eq!(
+        MyStructVersion::V1(MyStructV1(123)).upgrade_to::<MyStructV2>()?,
+        MyStructV2(123)
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_transitive_upgrade() {
+    #[derive(Debug, PartialEq, Clone)]
+    struct MyStructV1(u32);
+
+    #[derive(Debug, PartialEq, Clone)]
+    struct MyStructV2(u64);
+
+    #[derive(Debug, PartialEq, Clone)]
+    struct MyStructV3(String);
+
+    #[derive(Debug, PartialEq, Clone)]
+    struct MyStructV4(Vec<String>);
+
+    impl Upgrade<MyStructV2> for MyStructV1 {
+        fn upgrade(self: MyStructV1) -> MyStructV2 {
+            MyStructV2(self.0.into())
+        }
+    }
+
+    impl Upgrade<MyStructV3> for MyStructV2 {
+        fn upgrade(self: MyStructV2) -> MyStructV3 {
+            MyStructV3(self.0.to_string())
+        }
+    }
+
+    impl Upgrade<MyStructV4> for MyStructV3 {
+        fn upgrade(self: MyStructV3) -> MyStructV4 {
+            MyStructV4(vec![self.0])
+        }
+    }
+
+    #[derive(Debug, PartialEq, VersionedUpgrade, Clone)]
+    #[versioned(transitive, transitive_from)]
+    enum MyStructVersion {
+        V1(MyStructV1),
+        V2(MyStructV2),
+        V3(MyStructV3),
+        V4(MyStructV4),
+    }
+
+    let v3_struct: MyStructV3 = MyStructV1(123).upgrade();
+    assert_eq!(v3_struct, MyStructV3("123".to_string()));
+
+    let v4_struct: MyStructV4 = MyStructV2(123).upgrade();
+    assert_eq!(v4_struct, MyStructV4(vec!["123".to_string()]));
+
+    assert_eq!(MyStructV4::from(MyStructV1(123)), v4_struct);
+    assert_eq!(MyStructV2::from(MyStructV1(123)), MyStructV2(123));
+    assert_eq!(
+        MyStructVersion::V1(MyStructV1(123)).upgrade_to_latest(),
+        v4_struct
+    );
+
+    // Another enum can share the structures, as long as it leaves the
+    // transitive impls to the first.
+    #[derive(Debug, PartialEq, VersionedUpgrade, Clone)]
+    enum MyStructPrefixVersion {
+        V1(MyStructV1),
+        V2(MyStructV2),
+        V3(MyStructV3),
+    }
+
+    assert_eq!(
+        MyStructPrefixVersion::V1(MyStructV1(123)).upgrade_to_latest(),
+        v3_struct
+    );
+}
+
+#[test]
+fn test_versioned_serialize_as() -> Result<(), Box<dyn std::error::Error>> {
+    #[derive(Debug, PartialEq, Versioned, VersionedSerialize, VersionedDeserialize, Clone)]
+    enum MyOtherStructVersion {
+        #[versioned(version = 1)]
+        Current(MyStructV2),
+    }
+
+    assert_eq!(<MyStructV2 as VersionOf<MyStructVersion>>::VERSION, 2);
+    assert_eq!(<MyStructV2 as VersionOf<MyOtherStructVersion>>::VERSION, 1);
+
+    let v2_struct = MyStructV2 {
+        field1: "value1".to_string(),
+        new_field: "value2".to_string(),
+    };
+
+    let serialized_wrapper: serde_json::Value =
+        VersionOf::<MyStructVersion>::versioned_serialize_as(&v2_struct)?;
+
+    assert_eq!(
+        serialized_wrapper,
+        MyStructVersion::V2(v2_struct.clone()).versioned_serialize::<serde_json::Value>()?
+    );
+
+    let serialized_wrapper: serde_json::Value =
+        VersionOf::<MyOtherStructVersion>::versioned_serialize_as(&v2_struct)?;
+
+    assert_eq!(
+        serialized_wrapper,
+        serde_json::json!({
+            "version_number": 1,
+            "data": {
+                "field1": "value1",
+                "new_field": "value2"
+            }
+        })
+    );
+
+    assert_eq!(
+        MyOtherStructVersion::versioned_deserialize(&serialized_wrapper)?,
+        MyOtherStructVersion::Current(v2_struct)
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_version_of_single_family() -> Result<(), Box<dyn std::error::Error>> {
+    #[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+    struct MyStructV1 {
+        field1: String,
+    }
+
+    #[derive(Debug, PartialEq, Versioned, VersionedSerialize, VersionedDeserialize, Clone)]
+    enum MyStructVersion {
+        V1(MyStructV1),
+    }
+
+    let v1_struct = MyStructV1 {
+        field1: "value1".to_string(),
+    };
+
+    let serialized_wrapper: MsgPackBytes = v1_struct.versioned_serialize_as()?;
+
+    assert_eq!(
+        MyStructV1::from_versioned(MyStructVersion::versioned_deserialize(&serialized_wrapper)?),
+        Ok(v1_struct)
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_inline_variants() -> Result<(), Box<dyn std::error::Error>> {
+    #[derive(
+        Debug,
+        PartialEq,
+        Versioned,
+        VersionedUpgrade,
+        VersionedDowngrade,
+        VersionedSerialize,
+        VersionedDeserialize,
+        Clone,
+    )]
+    enum InlineVersion {
+        V0,
+        V1(String, u32),
+        V2 { field1: String, count: u32 },
+        V3(MyStructV1),
+    }
+
+    impl Upgrade<InlineVersionV1> for InlineVersionV0 {
+        fn upgrade(self: InlineVersionV0) -> InlineVersionV1 {
+            InlineVersionV1("default_value".to_string(), 0)
+        }
+    }
+
+    impl Upgrade<InlineVersionV2> for InlineVersionV1 {
+        fn upgrade(self: InlineVersionV1) -> InlineVersionV2 {
+            InlineVersionV2 {
+                field1: self.0,
+                count: self.1,
+            }
+        }
+    }
+
+    impl Upgrade<MyStructV1> for InlineVersionV2 {
+        fn upgrade(self: InlineVersionV2) -> MyStructV1 {
+            MyStructV1 {
+                field1: format!("{}_{}", self.field1, self.count),
+            }
+        }
+    }
+
+    impl Downgrade<InlineVersionV2> for MyStructV1 {
+        fn downgrade(self: MyStructV1) -> InlineVersionV2 {
+            InlineVersionV2 {
+                field1: self.field1,
+                count: 0,
+            }
+        }
+    }
+
+    impl Downgrade<InlineVersionV1> for InlineVersionV2 {
+        fn downgrade(self: InlineVersionV2) -> InlineVersionV1 {
+            InlineVersionV1(self.field1, self.count)
+        }
+    }
+
+    impl Downgrade<InlineVersionV0> for InlineVersionV1 {
+        fn downgrade(self: InlineVersionV1) -> InlineVersionV0 {
+            InlineVersionV0
+        }
+    }
+
+    let versions = [
+        (InlineVersion::V0, serde_json::json!(null)),
+        (
+            InlineVersion::V1("value1".to_string(), 1),
+            serde_json::json!(["value1", 1]),
+        ),
+        (
+            InlineVersion::V2 {
+                field1: "value1".to_string(),
+                count: 2,
+            },
+            serde_json::json!({ "field1": "value1", "count": 2 }),
+        ),
+    ];
+
+    for (version_number, (versioned, data)) in versions.into_iter().enumerate() {
+        let serialized_wrapper: serde_json::Value = versioned.versioned_serialize()?;
+        assert_eq!(
+            serialized_wrapper,
+            serde_json::json!({ "version_number": version_number, "data": data })
+        );
+        assert_eq!(
+            InlineVersion::versioned_deserialize(&serialized_wrapper)?,
+            versioned
+        );
+
+        let serialized_wrapper: MsgPackBytes = versioned.versioned_serialize()?;
+        assert_eq!(
+            InlineVersion::versioned_deserialize(&serialized_wrapper)?,
+            versioned
+        );
+    }
+
+    assert_eq!(InlineVersion::V0.upgrade_to_latest(), MyStructV1 {
+        field1: "default_value_0".to_string(),
+    });
+
+    assert_eq!(
+        InlineVersion::V1("value1".to_string(), 1).upgrade_to_version(2)?,
+        InlineVersion::V2 {
+            field1: "value1".to_string(),
+            count: 1,
+        }
+    );
+
+    assert_eq!(
+        InlineVersion::V1("value1".to_string(), 1).upgrade_to::<InlineVersionV2>()?,
+        InlineVersionV2 {
+            field1: "value1".to_string(),
+            count: 1,
+        }
+    );
+
+    let v3_struct = InlineVersion::V3(MyStructV1 {
+        field1: "value1".to_string(),
+    });
+
+    assert_eq!(
+        v3_struct.clone().downgrade_to_version(1)?,
+        InlineVersion::V1("value1".to_string(), 0)
+    );
+    assert_eq!(v3_struct.downgrade_to_version(0)?, InlineVersion::V0);
+
+    Ok(())
+}
+
+#[test]
+fn test_inline_variants_with_only_versioned_downgrade() -> Result<(), Box<dyn std::error::Error>> {
+    #[derive(Debug, PartialEq, Versioned, VersionedDowngrade, Clone)]
+    enum InlineVersion {
+        V0,
+        V1 { field1: String },
+        V2(MyStructV1),
+    }
+
+    impl Downgrade<InlineVersionV1> for MyStructV1 {
+        fn downgrade(self: MyStructV1) -> InlineVersionV1 {
+            InlineVersionV1 {
+                field1: self.field1,
+            }
+        }
+    }
+
+    impl Downgrade<InlineVersionV0> for InlineVersionV1 {
+        fn downgrade(self: InlineVersionV1) -> InlineVersionV0 {
+            InlineVersionV0
+        }
+    }
+
+    let v2_struct = InlineVersion::V2(MyStructV1 {
+        field1: "value1".to_string(),
+    });
+
+    assert_eq!(
+        v2_struct.clone().downgrade_to_version(1)?,
+        InlineVersion::V1 {
+            field1: "value1".to_string(),
+        }
+    );
+    assert_eq!(v2_struct.downgrade_to_version(0)?, InlineVersion::V0);
+
+    Ok(())
+}
+
+#[test]
+fn test_envelope_field_names() -> Result<(), Box<dyn std::error::Error>> {
+    #[derive(Debug, PartialEq, Versioned, VersionedSerialize, VersionedDeserialize, Clone)]
+    #[versioned(tag = "v", content = "payload")]
+    enum MyStructVersion {
+        V1(MyStructV1),
+        V2(MyStructV2),
+    }
+
+    let v2_struct = MyStructV2 {
+        field1: "value1".to_string(),
+        new_field: "value2".to_string(),
+    };
+
+    let serialized_wrapper: serde_json::Value =
+        MyStructVersion::V2(v2_struct.clone()).versioned_serialize()?;
+
+    assert_eq!(
+        serialized_wrapper,
+        serde_json::json!({
+            "v": 2,
+            "payload": {
+                "field1": "value1",
+                "new_field": "value2"
+            }
+        })
+    );
+
+    assert_eq!(
+        VersionOf::<MyStructVersion>::versioned_serialize_as::<serde_json::Value>(&v2_struct)?,
+        serialized_wrapper
+    );
+
+    let value = serde_json::json!({
+        "payload": { "field1": "value1" },
+        "sent_at": "2023-05-09",
+        "v": 1
+    });
+
+    assert_eq!(
+        MyStructVersion::versioned_deserialize(&value)?,
+        MyStructVersion::V1(MyStructV1 {
+            field1: "value1".to_string(),
+        })
+    );
+
+    let value = serde_json::json!({ "version_number": 1, "data": { "field1": "value1" } });
+    assert!(MyStructVersion::versioned_deserialize(&value).is_err());
+
+    let serialized_wrapper: MsgPackBytes =
+        MyStructVersion::V2(v2_struct.clone()).versioned_serialize()?;
+    assert_eq!(
+        MyStructVersion::versioned_deserialize(&serialized_wrapper)?,
+        MyStructVersion::V2(v2_struct)
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_internally_tagged() -> Result<(), Box<dyn std::error::Error>> {
+    #[derive(Debug, PartialEq, Versioned, VersionedSerialize, VersionedDeserialize, Clone)]
+    #[versioned(internally_tagged = "version")]
+    enum MyStructVersion {
+        V1 { field1: String },
+        V2(MyStructV2),
+    }
+
+    let v2_struct = MyStructV2 {
+        field1: "value1".to_string(),
+        new_field: "value2".to_string(),
+    };
+
+    let serialized_wrapper: serde_json::Value =
+        MyStructVersion::V2(v2_struct.clone()).versioned_serialize()?;
+
+    assert_eq!(
+        serialized_wrapper,
+        serde_json::json!({
+            "version": 2,
+            "field1": "value1",
+            "new_field": "value2"
+        })
+    );
+
+    assert_eq!(
+        MyStructVersion::versioned_deserialize(&serialized_wrapper)?,
+        MyStructVersion::V2(v2_struct.clone())
+    );
+
+    assert_eq!(
+        VersionOf::<MyStructVersion>::versioned_serialize_as::<serde_json::Value>(&v2_struct)?,
+        serialized_wrapper
+    );
+
+    let value = serde_json::json!({ "field1": "value1", "version": 1 });
+    assert_eq!(
+        MyStructVersion::versioned_deserialize(&value)?,
+        MyStructVersion::V1 {
+            field1: "value1".to_string(),
+        }
+    );
+
+    let value = serde_json::json!({ "field1": "value1" });
+    assert!(MyStructVersion::versioned_deserialize(&value).is_err());
+
+    assert!(MyStructVersion::V2(v2_struct)
+        .versioned_serialize::<MsgPackBytes>()
+        .is_err());
+
+    Ok(())
+}
+
+#[test]
+fn test_unversioned() -> Result<(), Box<dyn std::error::Error>> {
+    #[derive(Debug, PartialEq, VersionedSerialize, VersionedDeserialize, Clone)]
+    #[versioned(unversioned = 1)]
+    enum MyStructVersion {
+        V1(MyStructV1),
+        V2(MyStructV2),
+    }
+
+    let v1_struct = MyStructV1 {
+        field1: "value1".to_string(),
+    };
+
+    let v2_struct = MyStructV2 {
+        field1: "value1".to_string(),
+        new_field: "value2".to_string(),
+    };
+
+    let records = [
+        serde_json::to_value(&v1_struct)?,
+        MyStructVersion::V2(v2_struct.clone()).versioned_serialize()?,
+    ];
+
+    assert_eq!(
+        records
+            .iter()
+            .map(MyStructVersion::versioned_deserialize)
+            .collect::<Result<Vec<_>, _>>()?,
+        vec![
+            MyStructVersion::V1(v1_struct.clone()),
+            MyStructVersion::V2(v2_struct)
+        ]
+    );
+
+    let legacy = MsgPackBytes::serialize_format(&v1_struct)?;
+    assert_eq!(
+        MyStructVersion::versioned_deserialize(&legacy)?,
+        MyStructVersion::V1(v1_struct)
+    );
+
+    assert!(MyStructVersion::versioned_deserialize(&serde_json::json!({ "field2": 1 })).is_err());
+
+    Ok(())
+}
+
+#[test]
+fn test_unversioned_with_invalid_version() {
+    #[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+    struct MyStruct {
+        #[serde(default)]
+        x: u32,
+    }
+
+    #[derive(Debug, PartialEq, VersionedSerialize, VersionedDeserialize, Clone)]
+    #[versioned(unversioned = 1)]
+    enum MyStructVersion {
+        V1(MyStruct),
+    }
+
+    // Not a version number, so the envelope cannot be decoded, but it is still
+    // not unversioned data.
+    let invalid = serde_json::json!({ "version_number": -1, "data": { "x": 5 } });
+
+    assert!(MyStructVersion::versioned_deserialize(&invalid).is_err());
+    assert!(MyStructVersion::versioned_deserialize_or_unversioned(&invalid, 1).is_err());
+}
+
+#[test]
+fn test_unversioned_with_unknown_version() {
+    #[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+    struct MyStruct {
+        #[serde(default)]
+        x: u32,
+    }
+
+    #[derive(Debug, PartialEq, VersionedSerialize, VersionedDeserialize, Clone)]
+    #[versioned(unversioned = 1, repr = u8)]
+    enum MyStructVersion {
+        V1(MyStruct),
+    }
+
+    // Too large for the `repr`, so the envelope cannot be decoded, but it is
+    // still not unversioned data.
+    let newer = serde_json::json!({ "version_number": 300, "data": { "x": 5 } });
+
+    assert_eq!(
+        MyStructVersion::versioned_deserialize(&newer)
+            .unwrap_err()
+            .to_string(),
+        "Unknown version number 300 of \
+         `basic_tests::test_unversioned_with_unknown_version::MyStructVersion`; supported \
+         versions are 1"
+    );
+    assert!(MyStructVersion::versioned_deserialize_or_unversioned(&newer, 1).is_err());
+    assert!(matches!(
+        MyStructVersion::try_versioned_deserialize(&newer),
+        Err(VersionedError::UnknownVersion {
+            version: RawVersion::Number(300),
+            ..
+        })
+    ));
+}
+
+#[test]
+fn test_deserialize_or_unversioned() -> Result<(), Box<dyn std::error::Error>> {
+    let legacy = serde_json::json!({ "field1": "value1", "new_field": "value2" });
+
+    assert!(MyStructVersion::versioned_deserialize(&legacy).is_err());
+    assert_eq!(
+        MyStructVersion::versioned_deserialize_or_unversioned(&legacy, 2)?,
+        MyStructVersion::V2(MyStructV2 {
+            field1: "value1".to_string(),
+            new_field: "value2".to_string(),
+        })
+    );
+
+    let serialized_wrapper: serde_json::Value = MyStructVersion::V1(MyStructV1 {
+        field1: "value1".to_string(),
+    })
+    .versioned_serialize()?;
+
+    assert_eq!(
+        MyStructVersion::versioned_deserialize_or_unversioned(&serialized_wrapper, 2)?,
+        MyStructVersion::versioned_deserialize(&serialized_wrapper)?
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_deserialize_or_unversioned_unknown_version() {
+    #[derive(Debug, PartialEq, VersionedSerialize, VersionedDeserialize, Clone)]
+    #[versioned(repr = u8)]
+    enum MyStructVersion {
+        V1(MyStructV1),
+    }
+
+    let legacy = serde_json::json!({ "field1": "value1" });
+
+    for version in [2, 300] {
+        assert_eq!(
+            MyStructVersion::versioned_deserialize_or_unversioned(&legacy, version)
+                .unwrap_err()
+                .to_string(),
+            format!(
+                "Unknown version number {} of \
+                 `basic_tests::test_deserialize_or_unversioned_unknown_version::MyStructVersion`; \
+                 supported versions are 1",
+                version
+            )
+        );
+    }
+}
+
+#[test]
+fn test_serde_impls() -> Result<(), Box<dyn std::error::Error>> {
+    #[derive(Debug, PartialEq, VersionedSerialize, VersionedDeserialize, Clone)]
+    #[versioned(serde)]
+    enum MyStructVersion {
+        V1(MyStructV1),
+        V2 { field1: String, count: u32 },
+        V3,
+    }
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Document {
+        current: MyStructVersion,
+        history: Vec<MyStructVersion>,
+        by_name: std::collections::BTreeMap<String, MyStructVersion>,
+    }
+
+    let v1 = MyStructVersion::V1(MyStructV1 {
+        field1: "value1".to_string(),
+    });
+    let v2 = MyStructVersion::V2 {
+        field1: "value2".to_string(),
+        count: 2,
+    };
+
+    let document = Document {
+        current: v2.clone(),
+        history: vec![v1.clone(), MyStructVersion::V3],
+        by_name: [("first".to_string(), v1.clone())].into_iter().collect(),
+    };
+
+    let serialized = serde_json::to_value(&document)?;
+
+    assert_eq!(
+        serialized["current"],
+        v2.versioned_serialize::<serde_json::Value>()?
+    );
+    assert_eq!(
+        serialized["history"][0],
+        serde_json::json!({ "version_number": 1, "data": { "field1": "value1" } })
+    );
+    assert_eq!(serde_json::from_value::<Document>(serialized)?, document);
+
+    let msgpack = rmp_serde::to_vec(&document)?;
+    assert_eq!(rmp_serde::from_slice::<Document>(&msgpack)?, document);
+
+    let data_first = r#"{"data": {"field1": "value1"}, "version_number": 1}"#;
+    assert_eq!(serde_json::from_str::<MyStructVersion>(data_first)?, v1);
+
+    let unknown = serde_json::json!({ "version_number": 4, "data": null });
+    assert!(serde_json::from_value::<MyStructVersion>(unknown).is_err());
+
+    Ok(())
+}
+
+#[test]
+fn test_serde_impls_internally_tagged() -> Result<(), Box<dyn std::error::Error>> {
+    #[derive(Debug, PartialEq, VersionedSerialize, VersionedDeserialize, Clone)]
+    #[versioned(serde, internally_tagged = "version", repr = u8)]
+    enum MyStructVersion {
+        V1(MyStructV1),
+        #[versioned(version = "2024-03")]
+        March(MyStructV2),
+    }
+
+    let march = MyStructVersion::March(MyStructV2 {
+        field1: "value1".to_string(),
+        new_field: "value2".to_string(),
+    });
+
+    let serialized = serde_json::to_value(vec![march.clone()])?;
+
+    assert_eq!(
+        serialized,
+        serde_json::json!([{
+            "version": "2024-03",
+            "field1": "value1",
+            "new_field": "value2"
+        }])
+    );
+    assert_eq!(
+        serde_json::from_value::<Vec<MyStructVersion>>(serialized)?,
+        vec![march]
+    );
+
+    let tag_last = r#"{"field1": "value1", "version": 1}"#;
+    assert_eq!(
+        serde_json::from_str::<MyStructVersion>(tag_last)?,
+        MyStructVersion::V1(MyStructV1 {
+            field1: "value1".to_string()
+        })
+    );
+
+    let missing_tag = r#"{"field1": "value1"}"#;
+    assert!(serde_json::from_str::<MyStructVersion>(missing_tag).is_err());
+
+    Ok(())
+}
+
+#[test]
+fn test_latest_field() -> Result<(), Box<dyn std::error::Error>> {
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Document {
+        #[serde(with = "pro_serde_versioned::latest::<MyStructVersion>")]
+        config: MyStructV3,
+    }
+
+    let v1_document = serde_json::json!({
+        "config": { "version_number": 1, "data": { "field1": "value1" } }
+    });
+
+    let document: Document = serde_json::from_value(v1_document)?;
+    let latest = MyStructV3 {
+        field1: "VALUE1".to_string(),
+        new_field: "default_value".to_string(),
+        second_new_field: "default_value_v3".to_string(),
+    };
+
+    assert_eq!(document, Document {
+        config: latest.clone()
+    });
+    assert_eq!(
+        serde_json::to_value(&document)?["config"],
+        MyStructVersion::V3(latest).versioned_serialize::<serde_json::Value>()?
+    );
+
+    let msgpack = rmp_serde::to_vec(&document)?;
+    assert_eq!(rmp_serde::from_slice::<Document>(&msgpack)?, document);
+
+    let unknown = serde_json::json!({ "config": { "version_number": 9, "data": {} } });
+    assert!(serde_json::from_value::<Document>(unknown).is_err());
+
+    Ok(())
+}
+
+#[test]
+fn test_versioned_error() -> Result<(), Box<dyn std::error::Error>> {
+    let newer = serde_json::json!({ "version_number": 9, "data": {} });
+    let error = MyStructVersion::try_versioned_deserialize(&newer).unwrap_err();
+
+    assert!(matches!(error, VersionedError::UnknownVersion {
+        version: RawVersion::Number(9),
+        supported: &[1, 2, 3],
+        ..
+    }));
+    assert!(error.is_newer_version());
+    assert!(error.family().ends_with("MyStructVersion"));
+    assert!(MyStructVersion::versioned_deserialize(&newer)
+        .unwrap_err()
+        .to_string()
+        .contains(&error.to_string()));
+
+    let older = serde_json::json!({ "version_number": 0, "data": {} });
+    let error = MyStructVersion::try_versioned_deserialize(&older).unwrap_err();
+    assert!(matches!(error, VersionedError::UnknownVersion {
+        version: RawVersion::Number(0),
+        ..
+    }));
+    assert!(!error.is_newer_version());
+
+    let corrupt = serde_json::json!({ "version_number": 2, "data": { "field1": 1 } });
+    let error = MyStructVersion::try_versioned_deserialize(&corrupt).unwrap_err();
+    assert!(matches!(error, VersionedError::Decode {
+        version: Some(2),
+        ..
+    }));
+
+    let no_envelope = serde_json::json!({ "field1": "value1" });
+    let error = MyStructVersion::try_versioned_deserialize(&no_envelope).unwrap_err();
+    assert!(matches!(error, VersionedError::Decode {
+        version: None,
+        ..
+    }));
+
+    let v1: serde_json::Value = serde_json::from_str(V1_STRUCT)?;
+    assert_eq!(
+        MyStructVersion::try_versioned_deserialize_latest(&v1)?,
+        MyStructV3 {
+            field1: "VALUE1".to_string(),
+            new_field: "default_value".to_string(),
+            second_new_field: "default_value_v3".to_string(),
+        }
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_versioned_error_upgrade() {
+    #[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+    struct MyStructV2 {
+        field1: u32,
+    }
+
+    impl TryUpgrade<MyStructV2> for MyStructV1 {
+        type Error = std::num::ParseIntError;
+
+        fn try_upgrade(self: MyStructV1) -> Result<MyStructV2, Self::Error> {
+            Ok(MyStructV2 {
+                field1: self.field1.parse()?,
+            })
+        }
+    }
+
+    #[derive(Debug, PartialEq, VersionedUpgrade, VersionedDeserialize, Clone)]
+    #[versioned(fallible)]
+    enum MyStructVersion {
+        V1(MyStructV1),
+        V2(MyStructV2),
+    }
+
+    let v1 = serde_json::json!({ "version_number": 1, "data": { "field1": "value1" } });
+    let error = MyStructVersion::try_versioned_deserialize_latest(&v1).unwrap_err();
+
+    assert!(matches!(error, VersionedError::Upgrade {
+        from: 1,
+        to: 2,
+        ..
+    }));
+    assert!(error
+        .to_string()
+        .ends_with("from version 1 to version 2: invalid digit found in string"));
+}
+
+#[test]
+fn test_unknown_version() -> Result<(), Box<dyn std::error::Error>> {
+    #[derive(
+        Debug,
+        PartialEq,
+        VersionedUpgrade,
+        VersionedDowngrade,
+        VersionedSerialize,
+        VersionedDeserialize,
+        Clone,
+    )]
+    #[versioned(fallible)]
+    enum MyStructJsonVersion {
+        V1(MyStructV1),
+        V2(MyStructV2),
+        #[versioned(unknown)]
+        Unknown(RawEnvelope<serde_json::Value>),
+    }
+
+    #[derive(Debug, PartialEq, VersionedSerialize, VersionedDeserialize, Clone)]
+    enum MyStructMsgPackVersion {
+        V1(MyStructV1),
+        V2(MyStructV2),
+        #[versioned(unknown)]
+        Unknown(RawEnvelope<MsgPackBytes<'static>>),
+    }
+
+    let v3 = MyStructVersion::V3(MyStructV3 {
+        field1: "value1".to_string(),
+        new_field: "value2".to_string(),
+        second_new_field: "value3".to_string(),
+    });
+
+    let json = serde_json::to_string(&v3.versioned_serialize::<serde_json::Value>()?)?;
+    let value: serde_json::Value = serde_json::from_str(&json)?;
+    let unknown = MyStructJsonVersion::versioned_deserialize(&value)?;
+    assert_eq!(
+        unknown,
+        MyStructJsonVersion::Unknown(RawEnvelope {
+            version: RawVersion::Number(3),
+            data: value["data"].clone(),
+        })
+    );
+    assert_eq!(
+        serde_json::to_string(&unknown.versioned_serialize::<serde_json::Value>()?)?,
+        json
+    );
+    assert_eq!(
+        MyStructJsonVersion::try_versioned_deserialize(&value)?,
+        unknown
+    );
+
+    assert!(matches!(
+        unknown.clone().try_upgrade_to_latest(),
+        Err(UpgradeError::Undecoded {
+            version: RawVersion::Number(3)
+        })
+    ));
+    assert_eq!(
+        unknown.clone().downgrade_to_version(1),
+        Err(DowngradeError::Undecoded {
+            version: RawVersion::Number(3)
+        })
+    );
+    assert!(
+        MyStructJsonVersion::try_versioned_deserialize_latest(&value)
+            .unwrap_err()
+            .is_newer_version()
+    );
+
+    let v1: serde_json::Value = serde_json::from_str(V1_STRUCT)?;
+    assert_eq!(
+        MyStructJsonVersion::versioned_deserialize(&v1)?,
+        MyStructJsonVersion::V1(MyStructV1 {
+            field1: "value1".to_string()
+        })
+    );
+
+    let msgpack: MsgPackBytes = v3.versioned_serialize()?;
+    let unknown = MyStructMsgPackVersion::versioned_deserialize(&msgpack)?;
+    assert!(matches!(
+        unknown,
+        MyStructMsgPackVersion::Unknown(RawEnvelope {
+            version: RawVersion::Number(3),
+            ..
+        })
+    ));
+    assert_eq!(unknown.versioned_serialize::<MsgPackBytes>()?, msgpack);
+
+    Ok(())
+}
+
+#[test]
+fn test_unknown_version_serde() -> Result<(), Box<dyn std::error::Error>> {
+    #[derive(Debug, PartialEq, VersionedSerialize, VersionedDeserialize, Clone)]
+    #[versioned(serde)]
+    enum MyStructSerdeVersion {
+        V1(MyStructV1),
+        #[versioned(unknown)]
+        Unknown(RawEnvelope<serde_json::Value>),
+    }
+
+    let json = r#"{"version_number":2,"data":{"field1":"value1","new_field":"value2"}}"#;
+    let unknown: MyStructSerdeVersion = serde_json::from_str(json)?;
+    assert!(matches!(
+        unknown,
+        MyStructSerdeVersion::Unknown(RawEnvelope {
+            version: RawVersion::Number(2),
+            ..
+        })
+    ));
+    assert_eq!(serde_json::to_string(&unknown)?, json);
+
+    Ok(())
+}
+
+#[test]
+fn test_unknown_version_name() -> Result<(), Box<dyn std::error::Error>> {
+    #[derive(Debug, PartialEq, VersionedSerialize, VersionedDeserialize, Clone)]
+    #[versioned(serde)]
+    enum MyStructNamedVersion {
+        V1(MyStructV1),
+        #[versioned(version = "2024-03")]
+        March(MyStructV2),
+        #[versioned(unknown)]
+        Unknown(RawEnvelope<serde_json::Value>),
+    }
+
+    #[derive(Debug, PartialEq, VersionedSerialize, VersionedDeserialize, Clone)]
+    #[versioned(internally_tagged = "version", serde)]
+    enum MyStructTaggedVersion {
+        V1(MyStructV1),
+        #[versioned(version = "2024-03")]
+        March(MyStructV2),
+        #[versioned(unknown)]
+        Unknown(RawEnvelope<serde_json::Value>),
+    }
+
+    #[derive(Debug, PartialEq, VersionedSerialize, VersionedDeserialize, Clone)]
+    enum MyStructStrictVersion {
+        V1(MyStructV1),
+        #[versioned(version = "2024-03")]
+        March(MyStructV2),
+    }
+
+    #[derive(Debug, PartialEq, VersionedSerialize, VersionedDeserialize, Clone)]
+    enum MyStructNumberedVersion {
+        V1(MyStructV1),
+        #[versioned(unknown)]
+        Unknown(RawEnvelope<serde_json::Value>),
+    }
+
+    let json = r#"{"version_number":"2024-06","data":{"field1":"value1"}}"#;
+    let value: serde_json::Value = serde_json::from_str(json)?;
+    let unknown = MyStructNamedVersion::Unknown(RawEnvelope {
+        version: RawVersion::Name("2024-06".to_string()),
+        data: value["data"].clone(),
+    });
+
+    assert_eq!(
+        MyStructNamedVersion::versioned_deserialize(&value)?,
+        unknown
+    );
+    assert_eq!(
+        MyStructNamedVersion::try_versioned_deserialize(&value)?,
+        unknown
+    );
+    assert_eq!(unknown.versioned_serialize::<serde_json::Value>()?, value);
+    assert_eq!(serde_json::from_str::<MyStructNamedVersion>(json)?, unknown);
+    assert_eq!(serde_json::to_string(&unknown)?, json);
+
+    let tagged_json = r#"{"version":"2024-06","field1":"value1"}"#;
+    let tagged_value: serde_json::Value = serde_json::from_str(tagged_json)?;
+    let tagged_unknown = MyStructTaggedVersion::Unknown(RawEnvelope {
+        version: RawVersion::Name("2024-06".to_string()),
+        data: serde_json::json!({ "field1": "value1" }),
+    });
+    assert_eq!(
+        MyStructTaggedVersion::versioned_deserialize(&tagged_value)?,
+        tagged_unknown
+    );
+    assert_eq!(
+        tagged_unknown.versioned_serialize::<serde_json::Value>()?,
+        tagged_value
+    );
+    assert_eq!(
+        serde_json::from_str::<MyStructTaggedVersion>(tagged_json)?,
+        tagged_unknown
+    );
+    assert_eq!(serde_json::to_string(&tagged_unknown)?, tagged_json);
+
+    let error = MyStructStrictVersion::try_versioned_deserialize(&value).unwrap_err();
+    assert!(matches!(
+        &error,
+        VersionedError::UnknownVersion {
+            version: RawVersion::Name(name),
+            ..
+        } if name == "2024-06"
+    ));
+    assert!(!error.is_newer_version());
+    assert_eq!(
+        MyStructStrictVersion::versioned_deserialize(&value)
+            .unwrap_err()
+            .to_string(),
+        "Unknown version name \"2024-06\" of \
+         `basic_tests::test_unknown_version_name::MyStructStrictVersion`; supported versions are \
+         1, 2"
+    );
+
+    // Only enums with named versions can write a version name back out.
+    let misplaced = MyStructNumberedVersion::Unknown(RawEnvelope {
+        version: RawVersion::Name("2024-06".to_string()),
+        data: value["data"].clone(),
+    });
+    assert!(misplaced
+        .versioned_serialize::<serde_json::Value>()
+        .is_err());
+
+    Ok(())
+}
+
+fn assert_streams_json<E>(versioned: &E) -> Result<(), Box<dyn std::error::Error>>
+where
+    E: VersionedSerialize + WritesUnknown<serde_json::Value>,
+{
+    let value = versioned.versioned_serialize::<serde_json::Value>()?;
+
+    let mut streamed = Vec::new();
+    versioned.versioned_serialize_json(&mut streamed)?;
+    assert_eq!(streamed, serde_json::to_vec(&value)?);
+
+    // Written in declaration order, so only the values match.
+    let mut pretty = Vec::new();
+    versioned.versioned_serialize_into(&mut serde_json::Serializer::pretty(&mut pretty))?;
+    assert_eq!(serde_json::from_slice::<serde_json::Value>(&pretty)?, value);
+
+    Ok(())
+}
+
+#[test]
+fn test_versioned_serialize_into() -> Result<(), Box<dyn std::error::Error>> {
+    #[derive(Debug, PartialEq, VersionedSerialize, Clone)]
+    #[versioned(tag = "v", content = "payload", repr = u8)]
+    enum InlineVersion {
+        V0,
+        V1(String, u32),
+        V2 {
+            field1: String,
+            count: u32,
+        },
+        #[versioned(version = "2024-03")]
+        March(MyStructV1),
+    }
+
+    #[derive(Debug, PartialEq, VersionedSerialize, Clone)]
+    #[versioned(internally_tagged = "version")]
+    enum TaggedVersion {
+        V1 { field1: String },
+        V2(MyStructV2),
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+    enum Shape {
+        Point,
+        Circle(f32),
+        Line(u8, u8),
+        Rect { width: f32, height: f32 },
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+    struct Nested {
+        zebra: Option<u32>,
+        apple: std::collections::HashMap<u32, Vec<Shape>>,
+        mango: (bool, char),
+    }
+
+    #[derive(Debug, PartialEq, VersionedSerialize, Clone)]
+    enum NestedVersion {
+        V1(Nested),
+        #[versioned(unknown)]
+        Unknown(RawEnvelope<serde_json::Value>),
+    }
+
+    let v2_struct = MyStructV2 {
+        field1: "value1".to_string(),
+        new_field: "value2".to_string(),
+    };
+
+    assert_streams_json(&MyStructVersion::V1(MyStructV1 {
+        field1: "value1".to_string(),
+    }))?;
+    assert_streams_json(&MyStructVersion::V2(v2_struct.clone()))?;
+    assert_streams_json(&MyStructVersion::V3(MyStructV3 {
+        field1: "value1".to_string(),
+        new_field: "value2".to_string(),
+        second_new_field: "value3".to_string(),
+    }))?;
+
+    assert_streams_json(&InlineVersion::V0)?;
+    assert_streams_json(&InlineVersion::V1("value1".to_string(), 1))?;
+    assert_streams_json(&InlineVersion::V2 {
+        field1: "value1".to_string(),
+        count: 2,
+    })?;
+    assert_streams_json(&InlineVersion::March(MyStructV1 {
+        field1: "value1".to_string(),
+    }))?;
+
+    assert_streams_json(&TaggedVersion::V1 {
+        field1: "value1".to_string(),
+    })?;
+    assert_streams_json(&TaggedVersion::V2(v2_struct))?;
+
+    assert_streams_json(&NestedVersion::V1(Nested {
+        zebra: None,
+        apple: [
+            (10, vec![Shape::Point, Shape::Circle(0.5)]),
+            (2, vec![Shape::Line(1, 2), Shape::Rect {
+                width: 1.5,
+                height: 0.25,
+            }]),
+        ]
+        .into_iter()
+        .collect(),
+        mango: (true, 'x'),
+    }))?;
+    assert_streams_json(&NestedVersion::Unknown(RawEnvelope {
+        version: RawVersion::Number(9),
+        data: serde_json::json!({ "field1": "value1", "list": [1, 2.5, null] }),
+    }))?;
+
+    // `serde_json::Value` holds an `f32` as an `f64`, which is written with
+    // more digits.
+    let mut streamed = Vec::new();
+    NestedVersion::V1(Nested {
+        zebra: Some(1),
+        apple: [(1, vec![Shape::Circle(0.1)])].into_iter().collect(),
+        mango: (false, 'y'),
+    })
+    .versioned_serialize_json(&mut streamed)?;
+    assert_eq!(
+        String::from_utf8(streamed)?,
+        r#"{"data":{"apple":{"1":[{"Circle":0.10000000149011612}]},"mango":[false,"y"],"zebra":1},"version_number":1}"#
+    );
+
+    Ok(())
+}
@@ -0,0 +1,65 @@
+use pro_serde_versioned::{VersionedDeserialize, VersionedSerialize, VersionedUpgrade};
+
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+struct MyStructV1;
+
+#[derive(VersionedUpgrade)]
+#[versioned(fallible, transitive, unknown)]
+enum MyStructVersion {
+    V1(MyStructV1),
+}
+
+#[derive(VersionedSerialize)]
+#[versioned(tag = "data")]
+enum SameFieldsVersion {
+    V1(MyStructV1),
+}
+
+#[derive(VersionedSerialize)]
+#[versioned(tag = "", content = 1)]
+enum InvalidFieldsVersion {
+    V1(MyStructV1),
+}
+
+#[derive(VersionedSerialize)]
+#[versioned(internally_tagged = "version", content = "payload")]
+enum ConflictingTagsVersion {
+    V1(MyStructV1),
+}
+
+#[derive(VersionedSerialize)]
+#[versioned(internally_tagged = "version")]
+enum InternallyTaggedVersion {
+    V1(MyStructV1),
+    V2(MyStructV1, MyStructV1),
+    V3,
+}
+
+#[derive(VersionedDeserialize, Clone)]
+#[versioned(unversioned = 2)]
+enum UnversionedVersion {
+    V1(MyStructV1),
+}
+
+#[derive(VersionedDeserialize, Clone)]
+#[versioned(unversioned = "legacy")]
+enum UnversionedNameVersion {
+    #[versioned(version = "current")]
+    Current(MyStructV1),
+}
+
+#[derive(VersionedSerialize, Clone)]
+#[versioned(repr = i8)]
+enum SignedReprVersion {
+    V1(MyStructV1),
+}
+
+#[derive(VersionedSerialize, Clone)]
+#[versioned(repr = u8)]
+enum OutOfRangeVersion {
+    V1(MyStructV1),
+    #[versioned(version = 256)]
+    Current(MyStructV1),
+}
+
+fn main() {}
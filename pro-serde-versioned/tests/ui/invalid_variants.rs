@@ -0,0 +1,56 @@
+use pro_serde_versioned::{RawEnvelope, VersionedDeserialize, VersionedSerialize, VersionedUpgrade};
+
+#[derive(serde::Serialize, serde::Deserialize, Clone)]
+struct MyStructV1;
+
+#[derive(serde::Serialize, serde::Deserialize, Clone)]
+struct MyStructV2;
+
+#[derive(VersionedSerialize, VersionedDeserialize, Clone)]
+enum MyStructVersion {
+    V1(MyStructV1),
+    Legacy(MyStructV1),
+    VV2(MyStructV2),
+    #[versioned(version = 1)]
+    Current(MyStructV2),
+    #[versioned(verison = 3)]
+    V3(MyStructV2),
+}
+
+#[derive(VersionedSerialize, VersionedDeserialize, Clone)]
+enum NamedVersion {
+    #[versioned(version = "2024-03")]
+    March(MyStructV1),
+    #[versioned(version = "")]
+    April(MyStructV1),
+    #[versioned(version = "2024-03")]
+    May(MyStructV1),
+    V1(MyStructV2),
+}
+
+#[derive(VersionedSerialize, VersionedDeserialize, Clone)]
+enum UnknownVersion {
+    V1(MyStructV1),
+    #[versioned(unknown)]
+    Unknown,
+    #[versioned(unknown)]
+    Other(RawEnvelope<serde_json::Value>),
+    #[versioned(unknown, version = 2)]
+    V2(MyStructV2),
+}
+
+#[derive(VersionedUpgrade)]
+enum InfallibleUnknownVersion {
+    V1(MyStructV1),
+    #[versioned(unknown)]
+    Unknown(RawEnvelope<serde_json::Value>),
+}
+
+#[derive(VersionedSerialize, VersionedDeserialize, Clone)]
+enum GenericVersion<T> {
+    V1(T),
+    V2 { field1: T },
+    V3(T, MyStructV2),
+}
+
+fn main() {}
@@ -0,0 +1,19 @@
+use pro_serde_versioned::{Upgrade, VersionedUpgrade};
+
+struct MyStructV1;
+
+struct MyStructV3;
+
+impl Upgrade<MyStructV3> for MyStructV1 {
+    fn upgrade(self) -> MyStructV3 {
+        MyStructV3
+    }
+}
+
+#[derive(VersionedUpgrade)]
+enum MyStructVersion {
+    V1(MyStructV1),
+    V3(MyStructV3),
+}
+
+fn main() {}
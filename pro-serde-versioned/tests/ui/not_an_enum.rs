@@ -0,0 +1,8 @@
+use pro_serde_versioned::VersionedSerialize;
+
+#[derive(VersionedSerialize)]
+struct MyStructVersion {
+    field1: String,
+}
+
+fn main() {}
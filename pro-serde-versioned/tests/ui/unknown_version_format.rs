@@ -0,0 +1,20 @@
+use pro_serde_versioned::{
+    MsgPackBytes, RawEnvelope, VersionedDeserialize, VersionedSerialize,
+};
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Clone)]
+struct MyStructV1;
+
+#[derive(VersionedSerialize, VersionedDeserialize, Clone)]
+enum MyStructVersion {
+    V1(MyStructV1),
+    #[versioned(unknown)]
+    Unknown(RawEnvelope<serde_json::Value>),
+}
+
+fn main() {
+    let msgpack = MsgPackBytes(Vec::new().into());
+    let versioned = MyStructVersion::versioned_deserialize(&msgpack).unwrap();
+    let _: MsgPackBytes = versioned.versioned_serialize().unwrap();
+}